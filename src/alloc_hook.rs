@@ -0,0 +1,61 @@
+//! Process-wide callback told about every ring backing store that is
+//! allocated or freed, so memory profilers can attribute ring memory instead
+//! of seeing anonymous `std::alloc` blocks. Reallocation by
+//! `RingQueue::shrink` shows up as an allocation followed by a deallocation.
+//!
+//! Queues are identified by their backing store address, the same one the
+//! registry and the trace recorder report. A `RingGroup` shows up as one
+//! allocation covering all of its rings, identified by its start address,
+//! and a `SoaRingQueue` as one allocation per non-empty column.
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocEvent {
+  Alloc { queue: usize, size: usize },
+  Dealloc { queue: usize, size: usize },
+}
+
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Installs `hook`, replacing any previous one. It runs on the thread that
+/// creates or disposes the queue and must not create or dispose queues itself.
+pub fn set_alloc_hook(hook: fn(AllocEvent)) {
+  HOOK.store(hook as *mut (), Ordering::Release);
+}
+pub fn clear_alloc_hook() {
+  HOOK.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+pub(crate) fn notify(event: AllocEvent) {
+  let hook = HOOK.load(Ordering::Acquire);
+  if hook.is_null() { return }
+  let hook = unsafe { core::mem::transmute::<*mut (), fn(AllocEvent)>(hook) };
+  hook(event);
+}
+
+#[test]
+fn hook_sees_alloc_and_dealloc_of_a_queue() {
+  use std::cell::RefCell;
+  // per thread, so rings that tests running in parallel create and free
+  // while the hook is installed don't show up here
+  std::thread_local! {
+    static EVENTS: RefCell<Vec<AllocEvent>> = const { RefCell::new(Vec::new()) };
+  }
+  set_alloc_hook(|event| EVENTS.with(|events| events.borrow_mut().push(event)));
+  let queue = crate::RingQueue::<u64>::new(6);
+  let addr = queue.backing_store().addr();
+  unsafe { queue.dispose() };
+  let group = crate::RingGroup::<u64>::new(&[2, 3]);
+  let (group_memory, group_layout) = group.memory();
+  drop(group);
+  clear_alloc_hook();
+  let size = crate::RingQueue::<u64>::memory_layout(6).size();
+  let (group_addr, group_size) = (group_memory.addr().get(), group_layout.size());
+  assert!(EVENTS.with(|events| events.take()) == [
+    AllocEvent::Alloc { queue: addr, size },
+    AllocEvent::Dealloc { queue: addr, size },
+    AllocEvent::Alloc { queue: group_addr, size: group_size },
+    AllocEvent::Dealloc { queue: group_addr, size: group_size },
+  ]);
+}
@@ -0,0 +1,173 @@
+//! Ring wrappers for audio callbacks: the consumer side fills each output
+//! buffer completely, padding with silence on underrun, and both sides count
+//! the glitches they cause.
+
+use crate::{Consumer, Producer, RingQueue};
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Sample or frame type that has a silent value.
+pub trait Sample: Copy {
+  const SILENCE: Self;
+}
+impl Sample for f32 { const SILENCE: Self = 0.0; }
+impl Sample for f64 { const SILENCE: Self = 0.0; }
+impl Sample for i16 { const SILENCE: Self = 0; }
+impl Sample for i32 { const SILENCE: Self = 0; }
+impl <S: Sample, const N: usize> Sample for [S; N] { const SILENCE: Self = [S::SILENCE; N]; }
+
+/// Glitch counters, readable from any thread while the callback runs.
+#[derive(Debug, Default)]
+pub struct AudioStats {
+  /// Callbacks that found fewer items queued than the buffer needed.
+  pub underruns: AtomicU64,
+  /// Items replaced with silence by those callbacks.
+  pub silence_filled: AtomicU64,
+  /// Writes that found the ring too full to take everything.
+  pub overruns: AtomicU64,
+  /// Items dropped by those writes.
+  pub dropped: AtomicU64,
+}
+
+/// Consumer side, meant to be called from the device's output callback.
+pub struct AudioConsumer<T: Sample> {
+  consumer: Consumer<T>,
+  stats: Arc<AudioStats>,
+}
+
+/// Producer side, feeding the callback.
+pub struct AudioProducer<T: Sample> {
+  producer: Producer<T>,
+  stats: Arc<AudioStats>,
+}
+
+impl <T: Sample> Consumer<T> {
+  pub fn into_audio(self) -> AudioConsumer<T> {
+    AudioConsumer { consumer: self, stats: Arc::default() }
+  }
+}
+
+impl <T: Sample> Producer<T> {
+  pub fn into_audio(self) -> AudioProducer<T> {
+    AudioProducer { producer: self, stats: Arc::default() }
+  }
+}
+
+impl <T: Sample> AudioConsumer<T> {
+  /// Fills all of `out`, with silence past the queued items.
+  /// Returns how many items came from the ring.
+  pub fn fill(&mut self, out: &mut [T]) -> usize {
+    let read = self.consumer.pop_slice(out);
+    if read < out.len() {
+      out[read ..].fill(T::SILENCE);
+      self.stats.underruns.fetch_add(1, Ordering::Relaxed);
+      self.stats.silence_filled.fetch_add((out.len() - read) as u64, Ordering::Relaxed);
+    }
+    return read
+  }
+  pub fn stats(&self) -> Arc<AudioStats> {
+    self.stats.clone()
+  }
+}
+
+impl <T: Sample> AudioProducer<T> {
+  /// Queues as much of `items` as fits and drops the rest.
+  /// Returns how many items were queued.
+  pub fn write(&mut self, items: &[T]) -> usize {
+    let written = self.producer.push_slice(items);
+    if written < items.len() {
+      self.stats.overruns.fetch_add(1, Ordering::Relaxed);
+      self.stats.dropped.fetch_add((items.len() - written) as u64, Ordering::Relaxed);
+    }
+    return written
+  }
+  pub fn stats(&self) -> Arc<AudioStats> {
+    self.stats.clone()
+  }
+}
+
+/// Block of `SAMPLES` samples for each of `CHANNELS` channels, stored planar.
+pub type Frame<const CHANNELS: usize, const SAMPLES: usize> = [[f32; SAMPLES]; CHANNELS];
+
+/// Queue of fixed-size planar frames that converts from and to interleaved
+/// buffers on the way in and out, for hosts and devices that disagree on
+/// the sample order.
+pub struct FrameQueue<const CHANNELS: usize, const SAMPLES: usize> {
+  frames: RingQueue<Frame<CHANNELS, SAMPLES>>,
+}
+pub struct FrameProducer<const CHANNELS: usize, const SAMPLES: usize> {
+  frames: Producer<Frame<CHANNELS, SAMPLES>>,
+}
+pub struct FrameConsumer<const CHANNELS: usize, const SAMPLES: usize> {
+  frames: Consumer<Frame<CHANNELS, SAMPLES>>,
+}
+
+impl <const CHANNELS: usize, const SAMPLES: usize> FrameQueue<CHANNELS, SAMPLES> {
+  /// Room for `capacity` frames.
+  pub fn new(capacity: usize) -> Self {
+    Self { frames: RingQueue::new(capacity) }
+  }
+  pub fn split(self) -> (FrameProducer<CHANNELS, SAMPLES>, FrameConsumer<CHANNELS, SAMPLES>) {
+    let (producer, consumer) = self.frames.split();
+    (FrameProducer { frames: producer }, FrameConsumer { frames: consumer })
+  }
+}
+
+impl <const CHANNELS: usize, const SAMPLES: usize> FrameProducer<CHANNELS, SAMPLES> {
+  #[must_use]
+  pub fn push(&mut self, frame: &Frame<CHANNELS, SAMPLES>) -> bool {
+    self.frames.push_clone(frame)
+  }
+  /// Queues `SAMPLES` interleaved sample groups of `CHANNELS` each,
+  /// split into channels in the slot. Returns false if the queue is full.
+  #[must_use]
+  pub fn push_interleaved(&mut self, samples: &[f32]) -> bool {
+    assert!(samples.len() == CHANNELS * SAMPLES, "Expected {} interleaved samples", CHANNELS * SAMPLES);
+    self.frames.push_with(|| core::array::from_fn(|channel| core::array::from_fn(|index| samples[index * CHANNELS + channel])))
+  }
+}
+
+impl <const CHANNELS: usize, const SAMPLES: usize> FrameConsumer<CHANNELS, SAMPLES> {
+  #[must_use]
+  pub fn pop(&mut self) -> Option<Frame<CHANNELS, SAMPLES>> {
+    self.frames.pop()
+  }
+  /// Interleaves the next frame into `out`, reading it in place.
+  /// Returns false, leaving `out` untouched, if the queue is empty.
+  #[must_use]
+  pub fn pop_interleaved(&mut self, out: &mut [f32]) -> bool {
+    assert!(out.len() == CHANNELS * SAMPLES, "Expected room for {} interleaved samples", CHANNELS * SAMPLES);
+    let Some(frame) = self.frames.pop_ref() else { return false };
+    for (channel, samples) in frame.iter().enumerate() {
+      for (index, sample) in samples.iter().enumerate() { out[index * CHANNELS + channel] = *sample }
+    }
+    return true
+  }
+}
+
+#[test]
+fn frames_convert_between_interleaved_and_planar() {
+  let (mut producer, mut consumer) = FrameQueue::<2, 3>::new(2).split();
+  assert!(producer.push_interleaved(&[1.0, -1.0, 2.0, -2.0, 3.0, -3.0]));
+  assert!(producer.push(&[[4.0, 5.0, 6.0], [-4.0, -5.0, -6.0]]));
+  assert!(!producer.push_interleaved(&[0.0; 6]));
+  assert!(consumer.pop() == Some([[1.0, 2.0, 3.0], [-1.0, -2.0, -3.0]]));
+  let mut out = [0.0; 6];
+  assert!(consumer.pop_interleaved(&mut out));
+  assert!(out == [4.0, -4.0, 5.0, -5.0, 6.0, -6.0]);
+  assert!(!consumer.pop_interleaved(&mut out));
+}
+
+#[test]
+fn underruns_are_zero_filled_and_counted() {
+  let (producer, consumer) = crate::RingQueue::<[f32; 2]>::new(4).split();
+  let (mut producer, mut consumer) = (producer.into_audio(), consumer.into_audio());
+  assert!(producer.write(&[[1.0, -1.0]; 6]) == 4);
+  let mut out = [[9.0; 2]; 3];
+  assert!(consumer.fill(&mut out) == 3);
+  assert!(consumer.fill(&mut out) == 1);
+  assert!(out == [[1.0, -1.0], [0.0, 0.0], [0.0, 0.0]]);
+  let (written, read) = (producer.stats(), consumer.stats());
+  assert!(written.overruns.load(Ordering::Relaxed) == 1 && written.dropped.load(Ordering::Relaxed) == 2);
+  assert!(read.underruns.load(Ordering::Relaxed) == 1 && read.silence_filled.load(Ordering::Relaxed) == 2);
+}
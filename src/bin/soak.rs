@@ -0,0 +1,110 @@
+//! Long-running soak test, built with `--features soak`:
+//!
+//! ```text
+//! cargo run --release --features soak --bin soak -- [seconds] [seed]
+//! ```
+//!
+//! Runs rounds of a producer and a consumer thread over a queue of random
+//! capacity, both moving random batch sizes and sleeping or yielding at
+//! random points, and checks that every item arrives once and in order and
+//! that the occupancy never exceeds the capacity.
+//!
+//! On Linux each side also runs at a random nice level, so the scheduler
+//! favours one over the other. Unprivileged processes can only lower their
+//! priority, so the levels are applied to the fresh threads of each round
+//! and never to the main thread. Elsewhere priorities are left alone.
+
+use atomic_spsc_queue::RingQueue;
+use std::time::{Duration, Instant};
+
+/// xorshift64*, good enough to shuffle timings without a dependency.
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+  fn below(&mut self, bound: u64) -> u64 {
+    self.next() % bound
+  }
+  /// Sometimes sleeps, sometimes yields, mostly carries on.
+  fn perturb(&mut self) {
+    match self.below(100) {
+      0 => std::thread::sleep(Duration::from_micros(self.below(200))),
+      1 ..= 4 => std::thread::yield_now(),
+      _ => (),
+    }
+  }
+}
+
+/// Lowers the calling thread to a random nice level between 0 and 10.
+#[cfg(target_os = "linux")]
+fn perturb_priority(rng: &mut Rng) {
+  let nice = rng.below(11) as i32;
+  // with PRIO_PROCESS, a thread id selects just that thread
+  unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) };
+}
+#[cfg(not(target_os = "linux"))]
+fn perturb_priority(_rng: &mut Rng) {}
+
+fn round(rng: &mut Rng) -> u64 {
+  let capacity = 1 + rng.below(1024) as usize;
+  let items = 1 + rng.below(200_000);
+  let (mut producer, mut consumer) = RingQueue::<u64>::new(capacity).split();
+  producer.publish_every(1 + rng.below(capacity as u64) as usize);
+  let mut producer_rng = Rng(rng.next() | 1);
+  let mut consumer_rng = Rng(rng.next() | 1);
+  std::thread::scope(|s| {
+    s.spawn(move || {
+      perturb_priority(&mut producer_rng);
+      let mut next = 0;
+      while next < items {
+        let batch = 1 + producer_rng.below(64);
+        for _ in 0 .. batch.min(items - next) {
+          if producer.push(next).is_err() { break }
+          next += 1;
+        }
+        producer_rng.perturb();
+      }
+    });
+    s.spawn(move || {
+      perturb_priority(&mut consumer_rng);
+      let mut expected = 0;
+      while expected < items {
+        let occupancy = consumer.occupancy();
+        assert!(occupancy <= capacity, "occupancy {occupancy} exceeds capacity {capacity}");
+        let mut batch = [0; 64];
+        let len = 1 + consumer_rng.below(64) as usize;
+        let count = consumer.pop_slice(&mut batch[.. len]);
+        for item in &batch[.. count] {
+          assert!(*item == expected, "expected item {expected}, got {item} (capacity {capacity})");
+          expected += 1;
+        }
+        consumer_rng.perturb();
+      }
+      assert!(consumer.pop().is_none(), "item past the end of the stream");
+    });
+  });
+  items
+}
+
+fn main() {
+  let mut args = std::env::args().skip(1);
+  let seconds = args.next().map_or(60, |arg| arg.parse().expect("seconds must be a number"));
+  let seed = args.next().map_or_else(
+    || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64,
+    |arg| arg.parse().expect("seed must be a number"),
+  );
+  println!("soak for {seconds}s with seed {seed}");
+  let mut rng = Rng(seed | 1);
+  let deadline = Instant::now() + Duration::from_secs(seconds);
+  let (mut rounds, mut items) = (0u64, 0u64);
+  while Instant::now() < deadline {
+    items += round(&mut rng);
+    rounds += 1;
+  }
+  println!("{rounds} rounds, {items} items, no violations");
+}
@@ -0,0 +1,136 @@
+use core::{cell::UnsafeCell, sync::atomic::{AtomicUsize, Ordering}};
+use std::sync::Arc;
+
+/// Bipartite byte buffer: unlike a wrapping byte ring, the producer is always
+/// handed one contiguous region of the size it asks for, or nothing, so
+/// packets can be built in place. When the tail of the buffer is too short
+/// the region starts over at the front, and the consumer skips the unused
+/// tail once it gets there.
+pub struct BipBuffer {
+  shared: Arc<BipShared>,
+}
+
+struct BipShared {
+  bytes: Box<[UnsafeCell<u8>]>,
+  /// end of the committed data, written by the producer
+  write: AtomicUsize,
+  /// start of the unread data, written by the consumer
+  read: AtomicUsize,
+  /// end of the valid data before the producer went back to the front
+  last: AtomicUsize,
+}
+unsafe impl Sync for BipShared {}
+
+impl BipShared {
+  fn region(&self, start: usize, end: usize) -> *mut [u8] {
+    let first = UnsafeCell::raw_get(self.bytes[start ..].as_ptr());
+    core::ptr::slice_from_raw_parts_mut(first, end - start)
+  }
+}
+
+pub struct BipProducer {
+  shared: Arc<BipShared>,
+  /// region handed out by the last `write_chunk`
+  granted: Option<(usize, usize)>,
+}
+
+pub struct BipConsumer {
+  shared: Arc<BipShared>,
+}
+
+impl BipBuffer {
+  pub fn new(capacity: usize) -> Self {
+    if capacity == 0 { panic!("Capacity must not be zero") }
+    let shared = BipShared {
+      bytes: (0 .. capacity).map(|_| UnsafeCell::new(0)).collect(),
+      write: AtomicUsize::new(0),
+      read: AtomicUsize::new(0),
+      last: AtomicUsize::new(0),
+    };
+    Self { shared: Arc::new(shared) }
+  }
+  pub fn split(self) -> (BipProducer, BipConsumer) {
+    (BipProducer { shared: self.shared.clone(), granted: None }, BipConsumer { shared: self.shared })
+  }
+}
+
+impl BipProducer {
+  /// A contiguous region of exactly `len` bytes to fill, or None if no such
+  /// region is free. Nothing is visible to the consumer until `commit`.
+  pub fn write_chunk(&mut self, len: usize) -> Option<&mut [u8]> {
+    let capacity = self.shared.bytes.len();
+    let write = self.shared.write.load(Ordering::Relaxed);
+    let read = self.shared.read.load(Ordering::Acquire);
+    let start = if write < read {
+      // already went back to the front; stop short of the unread data so the
+      // buffer never looks empty while full
+      if write + len < read { write } else { return None }
+    } else if write + len <= capacity {
+      write
+    } else if len < read {
+      0
+    } else {
+      return None
+    };
+    self.granted = Some((start, len));
+    Some(unsafe { &mut *self.shared.region(start, start + len) })
+  }
+  /// Publishes the first `count` bytes of the last `write_chunk`.
+  pub fn commit(&mut self, count: usize) {
+    let Some((start, len)) = self.granted.take() else { panic!("Committed without a write_chunk") };
+    assert!(count <= len, "Committed more bytes than were granted");
+    let capacity = self.shared.bytes.len();
+    let write = self.shared.write.load(Ordering::Relaxed);
+    let new_write = start + count;
+    if new_write < write && write != capacity {
+      // went back to the front: the data ends where the write index was
+      self.shared.last.store(write, Ordering::Release);
+    } else if new_write > self.shared.last.load(Ordering::Relaxed) {
+      // moved past the old end, so the whole buffer is in use again
+      self.shared.last.store(capacity, Ordering::Release);
+    }
+    self.shared.write.store(new_write, Ordering::Release);
+  }
+}
+
+impl BipConsumer {
+  /// Committed bytes that can be read in place, up to the end of the valid data.
+  pub fn read_chunk(&self) -> &[u8] {
+    let write = self.shared.write.load(Ordering::Acquire);
+    let last = self.shared.last.load(Ordering::Acquire);
+    let mut read = self.shared.read.load(Ordering::Relaxed);
+    if read == last && write < read {
+      read = 0;
+      self.shared.read.store(0, Ordering::Release);
+    }
+    let end = if write < read { last } else { write };
+    unsafe { &*self.shared.region(read, end) }
+  }
+  /// Hands the first `count` bytes of the last `read_chunk` back to the producer.
+  pub fn release(&mut self, count: usize) {
+    assert!(count <= self.read_chunk().len(), "Released more bytes than were readable");
+    let read = self.shared.read.load(Ordering::Relaxed);
+    self.shared.read.store(read + count, Ordering::Release);
+  }
+}
+
+#[test]
+fn regions_stay_contiguous_across_the_wrap() {
+  let (mut producer, mut consumer) = BipBuffer::new(10).split();
+  producer.write_chunk(8).unwrap().copy_from_slice(b"abcdefgh");
+  producer.commit(8);
+  assert!(consumer.read_chunk() == b"abcdefgh");
+  consumer.release(5);
+  // 2 bytes left at the tail and 5 at the front, less the one kept free
+  assert!(producer.write_chunk(5).is_none());
+  producer.write_chunk(3).unwrap().copy_from_slice(b"ijk");
+  producer.commit(3);
+  assert!(producer.write_chunk(2).is_none());
+  // the unused tail is skipped
+  assert!(consumer.read_chunk() == b"fgh");
+  consumer.release(3);
+  assert!(consumer.read_chunk() == b"ijk");
+  consumer.release(3);
+  assert!(consumer.read_chunk().is_empty());
+  assert!(producer.write_chunk(7).unwrap().len() == 7);
+}
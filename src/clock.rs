@@ -0,0 +1,40 @@
+/// Time source used by the deadline-based waits.
+///
+/// `Instant` only needs to be ordered, so on targets without `std::time`
+/// it can be a raw tick count read from a hardware timer.
+pub trait Clock {
+  type Instant: Copy + Ord;
+  fn now(&self) -> Self::Instant;
+}
+
+/// `Clock` backed by `std::time::Instant`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdClock;
+
+impl Clock for StdClock {
+  type Instant = std::time::Instant;
+  fn now(&self) -> Self::Instant {
+    std::time::Instant::now()
+  }
+}
+
+#[cfg(test)]
+pub(crate) struct MockClock {
+  pub(crate) ticks: core::cell::Cell<u64>,
+}
+#[cfg(test)]
+impl MockClock {
+  pub(crate) fn new() -> Self {
+    Self { ticks: core::cell::Cell::new(0) }
+  }
+}
+#[cfg(test)]
+impl Clock for MockClock {
+  type Instant = u64;
+  /// every observation advances the clock by one tick
+  fn now(&self) -> u64 {
+    let now = self.ticks.get();
+    self.ticks.set(now + 1);
+    return now
+  }
+}
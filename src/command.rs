@@ -0,0 +1,109 @@
+use crate::{Consumer, Producer, RingQueue};
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+struct Tagged<T> {
+  frame: u64,
+  command: T,
+}
+
+/// Queue of commands grouped into numbered frames, for handing work from a
+/// game thread to a render thread. The producer closes each frame with
+/// `end_frame`, and the consumer takes whole frames with `pop_frame`.
+pub struct CommandQueue<T> {
+  queue: RingQueue<Tagged<T>>,
+}
+
+pub struct CommandProducer<T> {
+  producer: Producer<Tagged<T>>,
+  frame: u64,
+  completed_frames: Arc<AtomicU64>,
+}
+
+pub struct CommandConsumer<T> {
+  consumer: Consumer<Tagged<T>>,
+  completed_frames: Arc<AtomicU64>,
+}
+
+impl <T> CommandQueue<T> {
+  pub fn new(capacity: usize) -> Self {
+    Self { queue: RingQueue::new(capacity) }
+  }
+  pub fn split(self) -> (CommandProducer<T>, CommandConsumer<T>) {
+    let (producer, consumer) = self.queue.split();
+    let completed_frames = Arc::new(AtomicU64::new(0));
+    (
+      CommandProducer { producer, frame: 0, completed_frames: completed_frames.clone() },
+      CommandConsumer { consumer, completed_frames },
+    )
+  }
+}
+
+impl <T> CommandProducer<T> {
+  /// Adds `command` to the current frame, handing it back if the queue is full.
+  pub fn push(&mut self, command: T) -> Result<(), T> {
+    self.producer.push(Tagged { frame: self.frame, command }).map_err(|tagged| tagged.command)
+  }
+  /// Frame that `push` currently adds to. Frames are numbered from 0.
+  pub fn current_frame(&self) -> u64 {
+    self.frame
+  }
+  /// Closes the current frame and returns its number.
+  pub fn end_frame(&mut self) -> u64 {
+    let ended = self.frame;
+    self.frame += 1;
+    self.completed_frames.store(self.frame, Ordering::Release);
+    return ended
+  }
+}
+
+impl <T> CommandConsumer<T> {
+  /// Number of frames the producer has closed; frame `n` is complete when `n < completed_frames()`.
+  pub fn completed_frames(&self) -> u64 {
+    self.completed_frames.load(Ordering::Acquire)
+  }
+  /// Passes every queued command of completed frames up to and including
+  /// `frame` to `handle`, stopping at the first command of a later frame or
+  /// of the frame the producer hasn't ended yet, so frames are only ever
+  /// handled whole. Returns how many commands were handled.
+  pub fn pop_frame(&mut self, frame: u64, mut handle: impl FnMut(T)) -> usize {
+    // commands of frames ended before this load are all published
+    let completed_frames = self.completed_frames();
+    let mut handled = 0;
+    while self.consumer.peek().is_some_and(|tagged| tagged.frame <= frame && tagged.frame < completed_frames) {
+      let Some(tagged) = self.consumer.pop() else { break };
+      handle(tagged.command);
+      handled += 1;
+    }
+    return handled
+  }
+}
+
+#[test]
+fn pop_frame_stops_at_the_fence() {
+  let (mut producer, mut consumer) = CommandQueue::<&str>::new(8).split();
+  producer.push("clear").unwrap();
+  producer.push("draw a").unwrap();
+  assert!(producer.end_frame() == 0);
+  producer.push("draw b").unwrap();
+  assert!(consumer.completed_frames() == 1);
+  let mut drawn = Vec::new();
+  assert!(consumer.pop_frame(0, |command| drawn.push(command)) == 2);
+  assert!(consumer.pop_frame(0, |command| drawn.push(command)) == 0);
+  producer.end_frame();
+  assert!(consumer.pop_frame(1, |command| drawn.push(command)) == 1);
+  assert!(drawn == ["clear", "draw a", "draw b"]);
+}
+
+#[test]
+fn unended_frame_is_not_yielded() {
+  let (mut producer, mut consumer) = CommandQueue::<&str>::new(8).split();
+  producer.push("clear").unwrap();
+  producer.push("draw a").unwrap();
+  let mut drawn = Vec::new();
+  assert!(consumer.pop_frame(0, |command| drawn.push(command)) == 0);
+  producer.push("draw b").unwrap();
+  producer.end_frame();
+  assert!(consumer.pop_frame(5, |command| drawn.push(command)) == 3);
+  assert!(drawn == ["clear", "draw a", "draw b"]);
+}
@@ -0,0 +1,74 @@
+use crate::Producer;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Producer that may only push while it holds credits granted by the
+/// downstream side, so backpressure follows actual processing rather than
+/// just ring occupancy. Every accepted item costs one credit.
+pub struct CreditedProducer<T> {
+  producer: Producer<T>,
+  credits: Arc<AtomicUsize>,
+}
+
+/// Handle for granting credits to a `CreditedProducer`, usually kept next to the consumer.
+#[derive(Clone)]
+pub struct Credits {
+  credits: Arc<AtomicUsize>,
+}
+
+/// Why a credited push was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreditError<T> {
+  /// No credits left; the item is handed back.
+  NoCredits(T),
+  /// The ring is full; the item is handed back and no credit was spent.
+  Full(T),
+}
+
+impl <T> Producer<T> {
+  pub fn with_credits(self, initial: usize) -> (CreditedProducer<T>, Credits) {
+    let credits = Arc::new(AtomicUsize::new(initial));
+    (CreditedProducer { producer: self, credits: credits.clone() }, Credits { credits })
+  }
+}
+
+impl <T> CreditedProducer<T> {
+  pub fn push(&mut self, item: T) -> Result<(), CreditError<T>> {
+    // only this side ever takes credits away, so the count can't drop to zero
+    // between the check and the decrement
+    if self.credits.load(Ordering::Acquire) == 0 { return Err(CreditError::NoCredits(item)) }
+    self.producer.push(item).map_err(CreditError::Full)?;
+    self.credits.fetch_sub(1, Ordering::AcqRel);
+    return Ok(())
+  }
+  pub fn available(&self) -> usize {
+    self.credits.load(Ordering::Acquire)
+  }
+  pub fn into_inner(self) -> Producer<T> {
+    self.producer
+  }
+}
+
+impl Credits {
+  pub fn grant(&self, count: usize) {
+    self.credits.fetch_add(count, Ordering::AcqRel);
+  }
+  pub fn available(&self) -> usize {
+    self.credits.load(Ordering::Acquire)
+  }
+}
+
+#[test]
+fn pushes_stop_when_credits_run_out() {
+  let (producer, mut consumer) = crate::RingQueue::<u8>::new(8).split();
+  let (mut producer, credits) = producer.with_credits(2);
+  assert!(producer.push(1).is_ok());
+  assert!(producer.push(2).is_ok());
+  assert!(producer.push(3) == Err(CreditError::NoCredits(3)));
+  // consuming alone frees ring space, not credits
+  assert!(consumer.pop() == Some(1));
+  assert!(producer.push(3) == Err(CreditError::NoCredits(3)));
+  credits.grant(1);
+  assert!(producer.push(3).is_ok());
+  assert!(credits.available() == 0);
+}
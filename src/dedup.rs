@@ -0,0 +1,61 @@
+use crate::Producer;
+
+/// Producer for state-update streams: `push_if_changed` skips values equal
+/// to the last one pushed, so the consumer only sees actual changes.
+pub struct DedupProducer<T> {
+  producer: Producer<T>,
+  /// copy of the most recently pushed value
+  last: Option<T>,
+}
+
+/// What `DedupProducer::push_if_changed` did with the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+  Pushed,
+  /// Equal to the last pushed value, so nothing was queued.
+  Unchanged,
+  /// The ring had no room; the last pushed value stays the one to compare against.
+  Rejected,
+}
+
+impl <T> Producer<T> {
+  pub fn into_dedup(self) -> DedupProducer<T> {
+    DedupProducer { producer: self, last: None }
+  }
+}
+
+impl <T: Clone + PartialEq> DedupProducer<T> {
+  pub fn push_if_changed(&mut self, item: &T) -> PushOutcome {
+    if self.last.as_ref() == Some(item) { return PushOutcome::Unchanged }
+    if !self.producer.push_clone(item) { return PushOutcome::Rejected }
+    self.last = Some(item.clone());
+    return PushOutcome::Pushed
+  }
+}
+
+impl <T> DedupProducer<T> {
+  /// Forgets the last pushed value, so the next push goes through even if
+  /// it repeats it, e.g. after the consumer asked for a full resync.
+  pub fn forget_last(&mut self) {
+    self.last = None;
+  }
+  pub fn into_inner(self) -> Producer<T> {
+    self.producer
+  }
+}
+
+#[test]
+fn repeated_values_are_not_queued() {
+  let (producer, mut consumer) = crate::RingQueue::<u32>::new(2).split();
+  let mut producer = producer.into_dedup();
+  assert!(producer.push_if_changed(&1) == PushOutcome::Pushed);
+  assert!(producer.push_if_changed(&1) == PushOutcome::Unchanged);
+  assert!(producer.push_if_changed(&2) == PushOutcome::Pushed);
+  assert!(producer.push_if_changed(&3) == PushOutcome::Rejected);
+  assert!(producer.push_if_changed(&2) == PushOutcome::Unchanged);
+  assert!(consumer.pop() == Some(1) && consumer.pop() == Some(2));
+  assert!(producer.push_if_changed(&3) == PushOutcome::Pushed);
+  producer.forget_last();
+  assert!(producer.push_if_changed(&3) == PushOutcome::Pushed);
+  assert!(consumer.pop() == Some(3) && consumer.pop() == Some(3));
+}
@@ -0,0 +1,80 @@
+//! Cross-checking against a reference queue, enabled by the `duplex-check`
+//! feature for staging runs before a path is switched over to the ring.
+//!
+//! Every pushed item is also appended to a `Mutex<VecDeque>`, and every item
+//! popped from the ring is compared with the one taken from the reference.
+//! A mismatch, or an item appearing in the ring only, panics with both
+//! values, which points at lost, duplicated or reordered items.
+
+use crate::{Consumer, Producer, RingQueue};
+use std::{collections::VecDeque, fmt::Debug, sync::{Arc, Mutex}};
+
+pub struct DuplexCheck<T> {
+  queue: RingQueue<T>,
+}
+pub struct CheckedProducer<T> {
+  producer: Producer<T>,
+  reference: Arc<Mutex<VecDeque<T>>>,
+}
+pub struct CheckedConsumer<T> {
+  consumer: Consumer<T>,
+  reference: Arc<Mutex<VecDeque<T>>>,
+}
+
+fn lock<T>(reference: &Mutex<VecDeque<T>>) -> std::sync::MutexGuard<'_, VecDeque<T>> {
+  reference.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl <T: Clone + PartialEq + Debug> DuplexCheck<T> {
+  pub fn new(capacity: usize) -> Self {
+    Self { queue: RingQueue::new(capacity) }
+  }
+  pub fn split(self) -> (CheckedProducer<T>, CheckedConsumer<T>) {
+    let (producer, consumer) = self.queue.split();
+    let reference = Arc::new(Mutex::new(VecDeque::new()));
+    (CheckedProducer { producer, reference: reference.clone() }, CheckedConsumer { consumer, reference })
+  }
+}
+
+impl <T: Clone + PartialEq + Debug> CheckedProducer<T> {
+  pub fn push(&mut self, item: T) -> Result<(), T> {
+    // the reference goes first, so the consumer never sees an item it lacks
+    let mut reference = lock(&self.reference);
+    reference.push_back(item.clone());
+    let pushed = self.producer.push(item);
+    if pushed.is_err() { reference.pop_back(); }
+    return pushed
+  }
+}
+
+impl <T: Clone + PartialEq + Debug> CheckedConsumer<T> {
+  #[must_use]
+  pub fn pop(&mut self) -> Option<T> {
+    let item = self.consumer.pop()?;
+    match lock(&self.reference).pop_front() {
+      Some(expected) if expected == item => (),
+      Some(expected) => panic!("Ring diverged from the reference queue: popped {item:?}, expected {expected:?}"),
+      None => panic!("Ring diverged from the reference queue: popped {item:?}, which was never pushed"),
+    }
+    return Some(item)
+  }
+}
+
+#[test]
+fn matching_streams_pass_the_check() {
+  let (mut producer, mut consumer) = DuplexCheck::<u32>::new(4).split();
+  std::thread::scope(|s| {
+    s.spawn(move || {
+      for mut item in 0 .. 1000 {
+        while let Err(rejected) = producer.push(item) { item = rejected }
+      }
+    });
+    let mut expected = 0;
+    while expected < 1000 {
+      if let Some(item) = consumer.pop() {
+        assert!(item == expected);
+        expected += 1;
+      }
+    }
+  });
+}
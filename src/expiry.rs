@@ -0,0 +1,45 @@
+use crate::{Clock, Consumer, Producer};
+
+/// Item queued together with the deadline after which it must not be processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiring<T, I> {
+  pub item: T,
+  pub deadline: I,
+}
+
+impl <T, I> Producer<Expiring<T, I>> {
+  /// Stamps `item` with `deadline`, usually the enqueue time plus the item's
+  /// time to live, and pushes it.
+  pub fn push_expiring(&mut self, item: T, deadline: I) -> Result<(), T> {
+    self.push(Expiring { item, deadline }).map_err(|rejected| rejected.item)
+  }
+}
+
+impl <T, I: Copy + Ord> Consumer<Expiring<T, I>> {
+  /// Pops the next item whose deadline `clock` has not reached yet, dropping
+  /// the stale ones in front of it. Returns the item, if any, and how many
+  /// items were dropped on the way.
+  pub fn pop_unexpired<C: Clock<Instant = I>>(&mut self, clock: &C) -> (Option<T>, usize) {
+    let now = clock.now();
+    let mut expired = 0;
+    while let Some(Expiring { item, deadline }) = self.pop() {
+      if now < deadline { return (Some(item), expired) }
+      expired += 1;
+    }
+    return (None, expired)
+  }
+}
+
+#[test]
+fn stale_items_are_dropped_on_pop() {
+  let clock = crate::clock::MockClock::new();
+  let (mut producer, mut consumer) = crate::RingQueue::<Expiring<u32, u64>>::new(8).split();
+  for (item, deadline) in [(0, 3), (1, 5), (2, 20), (3, 6), (4, 20)] {
+    assert!(producer.push_expiring(item, deadline).is_ok());
+  }
+  clock.ticks.set(5);
+  assert!(consumer.pop_unexpired(&clock) == (Some(2), 2));
+  // 3 sat behind a fresh item and has gone stale by the next pop, at tick 6
+  assert!(consumer.pop_unexpired(&clock) == (Some(4), 1));
+  assert!(consumer.pop_unexpired(&clock) == (None, 0));
+}
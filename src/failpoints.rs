@@ -0,0 +1,64 @@
+//! Failpoint injection for the queue's fallible paths.
+//!
+//! Compiled in for the crate's own tests and under the `failpoints` feature;
+//! otherwise `fail_point` is a constant `false`. Failpoints are armed per
+//! thread so that tests running in parallel do not trip each other.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failpoint {
+  /// Backing store allocation behaves as if the allocator returned null.
+  Alloc,
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+mod imp {
+  use super::Failpoint;
+  use std::cell::Cell;
+
+  const POINT_COUNT: usize = 1;
+
+  std::thread_local! {
+    static ARMED: [Cell<u32>; POINT_COUNT] = const { [const { Cell::new(0) }; POINT_COUNT] };
+  }
+
+  /// Makes the next `times` hits of `point` on the calling thread fail.
+  pub fn arm(point: Failpoint, times: u32) {
+    ARMED.with(|armed| armed[point as usize].set(times));
+  }
+  pub fn disarm(point: Failpoint) {
+    arm(point, 0)
+  }
+
+  #[inline(always)]
+  pub(crate) fn fail_point(point: Failpoint) -> bool {
+    ARMED.with(|armed| {
+      let remaining = armed[point as usize].get();
+      if remaining == 0 { return false }
+      armed[point as usize].set(remaining - 1);
+      return true
+    })
+  }
+}
+
+#[cfg(not(any(test, feature = "failpoints")))]
+mod imp {
+  use super::Failpoint;
+
+  #[inline(always)]
+  pub(crate) fn fail_point(_point: Failpoint) -> bool { false }
+}
+
+pub(crate) use imp::fail_point;
+#[cfg(any(test, feature = "failpoints"))]
+pub use imp::{arm, disarm};
+
+#[test]
+fn armed_point_fails_requested_times() {
+  arm(Failpoint::Alloc, 2);
+  assert!(fail_point(Failpoint::Alloc));
+  assert!(fail_point(Failpoint::Alloc));
+  assert!(!fail_point(Failpoint::Alloc));
+  arm(Failpoint::Alloc, 5);
+  disarm(Failpoint::Alloc);
+  assert!(!fail_point(Failpoint::Alloc));
+}
@@ -0,0 +1,88 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const BUCKETS: usize = u32::BITS as usize + 1;
+
+/// Occupancy samples in power-of-two buckets: bucket 0 counts empty rings
+/// and bucket `k` occupancies in `2^(k-1) .. 2^k`, with the last bucket also
+/// taking everything from `2^32` up on 64-bit. Filled by the consumer,
+/// see `Consumer::sample_occupancy`, and readable from any thread.
+#[derive(Debug)]
+pub struct OccupancyHistogram {
+  buckets: [AtomicU64; BUCKETS],
+}
+
+impl Default for OccupancyHistogram {
+  fn default() -> Self {
+    Self { buckets: [const { AtomicU64::new(0) }; BUCKETS] }
+  }
+}
+
+impl OccupancyHistogram {
+  /// Adds one sample. Also usable on its own, e.g. from a timer that reads
+  /// the consumer's `occupancy`.
+  #[inline]
+  pub fn record(&self, occupancy: usize) {
+    let bucket = ((usize::BITS - occupancy.leading_zeros()) as usize).min(BUCKETS - 1);
+    if let Some(count) = self.buckets.get(bucket) { count.fetch_add(1, Ordering::Relaxed); }
+  }
+  pub fn samples(&self) -> u64 {
+    self.buckets.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+  }
+  /// Upper bound of the occupancy below which `percent` of the samples lie,
+  /// rounded up to the bucket boundary; None before the first sample.
+  pub fn percentile(&self, percent: f64) -> Option<usize> {
+    let counts = self.buckets.each_ref().map(|count| count.load(Ordering::Relaxed));
+    let total = counts.iter().sum::<u64>();
+    if total == 0 { return None }
+    let rank = ((total as f64 * percent / 100.0).ceil() as u64).clamp(1, total);
+    let mut seen = 0;
+    for (bucket, count) in counts.into_iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        // the last bucket is open-ended
+        if bucket == BUCKETS - 1 { return Some(usize::MAX) }
+        return Some(((1u64 << bucket) - 1) as usize)
+      }
+    }
+    unreachable!()
+  }
+}
+
+/// Consumer-side state recording every `every`-th operation.
+pub(crate) struct Sampler {
+  pub(crate) histogram: Arc<OccupancyHistogram>,
+  pub(crate) every: usize,
+  pub(crate) countdown: usize,
+}
+
+impl Sampler {
+  #[inline]
+  pub(crate) fn tick(&mut self, occupancy: impl FnOnce() -> usize) {
+    self.countdown -= 1;
+    if self.countdown != 0 { return }
+    self.countdown = self.every;
+    self.histogram.record(occupancy());
+  }
+}
+
+#[test]
+fn percentiles_come_from_bucket_bounds() {
+  let histogram = OccupancyHistogram::default();
+  assert!(histogram.percentile(50.0).is_none());
+  for occupancy in [0, 1, 2, 3, 5, 6, 7, 7, 100, 1000] { histogram.record(occupancy) }
+  assert!(histogram.samples() == 10);
+  assert!(histogram.percentile(10.0) == Some(0));
+  assert!(histogram.percentile(50.0) == Some(7));
+  assert!(histogram.percentile(90.0) == Some(127));
+  assert!(histogram.percentile(100.0) == Some(1023));
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn huge_occupancies_land_in_the_last_bucket() {
+  let histogram = OccupancyHistogram::default();
+  for occupancy in [1, 1 << 32, usize::MAX] { histogram.record(occupancy) }
+  assert!(histogram.samples() == 3);
+  assert!(histogram.percentile(100.0) == Some(usize::MAX));
+}
@@ -0,0 +1,55 @@
+//! Hook points around every index load/store of the queue.
+//!
+//! Compiled in for the crate's own tests and under the `interleave-hooks`
+//! feature; otherwise `hook` is an empty inline function. A test harness
+//! installs a per-thread callback and blocks inside it to force a specific
+//! producer/consumer interleaving.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+  ProducerLoadWriteIndex,
+  ProducerLoadReadIndex,
+  ProducerStoreWriteIndex,
+  ConsumerLoadReadIndex,
+  ConsumerLoadWriteIndex,
+  ConsumerStoreReadIndex,
+}
+
+#[cfg(any(test, feature = "interleave-hooks"))]
+mod imp {
+  use super::HookPoint;
+  use std::cell::RefCell;
+
+  type ThreadHook = Box<dyn FnMut(HookPoint)>;
+
+  std::thread_local! {
+    static HOOK: RefCell<Option<ThreadHook>> = const { RefCell::new(None) };
+  }
+
+  /// Installs `hook` for the calling thread, replacing any previous one.
+  pub fn set_thread_hook(hook: impl FnMut(HookPoint) + 'static) {
+    HOOK.with(|slot| *slot.borrow_mut() = Some(Box::new(hook)));
+  }
+  pub fn clear_thread_hook() {
+    HOOK.with(|slot| *slot.borrow_mut() = None);
+  }
+
+  #[inline(always)]
+  pub(crate) fn hook(point: HookPoint) {
+    HOOK.with(|slot| {
+      if let Some(hook) = slot.borrow_mut().as_mut() { hook(point) }
+    });
+  }
+}
+
+#[cfg(not(any(test, feature = "interleave-hooks")))]
+mod imp {
+  use super::HookPoint;
+
+  #[inline(always)]
+  pub(crate) fn hook(_point: HookPoint) {}
+}
+
+pub(crate) use imp::hook;
+#[cfg(any(test, feature = "interleave-hooks"))]
+pub use imp::{set_thread_hook, clear_thread_hook};
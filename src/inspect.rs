@@ -0,0 +1,59 @@
+//! Decoding a queue's state from nothing but its backing store address, as
+//! reported by the registry, the trace recorder or the allocation hook, for
+//! debugger plugins and post-mortem tools.
+//!
+//! Only loads are issued, and nothing is locked or written, so inspecting a
+//! queue doesn't disturb the threads using it; the report is a snapshot that
+//! may be torn if they are active. The backing store doesn't record its own
+//! capacity, so the caller has to supply it.
+
+use crate::ring_queue::{bump_index, indexing_adjusted_capacity, Metadata, FROZEN_FLAG};
+use core::sync::atomic::Ordering;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueReport {
+  pub read_index: u32,
+  pub write_index: u32,
+  pub frozen: bool,
+  /// Slots holding queued items, in the order they will be popped; None if
+  /// the indices are out of range for the capacity, i.e. the metadata is
+  /// corrupt or the capacity is wrong.
+  pub queued_slots: Option<Vec<usize>>,
+}
+
+/// # Safety
+///
+/// `backing_store` must point just past the metadata of a ring, live or in a
+/// memory image, so that the metadata can be read.
+pub unsafe fn inspect(backing_store: *const (), capacity: usize) -> QueueReport {
+  let metadata = unsafe { &*backing_store.byte_sub(size_of::<Metadata>()).cast::<Metadata>() };
+  let read_index = metadata.read_index.load(Ordering::Relaxed);
+  let write_index = metadata.write_index.load(Ordering::Relaxed);
+  let frozen = metadata.flags.load(Ordering::Relaxed) & FROZEN_FLAG != 0;
+  let slots = indexing_adjusted_capacity(capacity);
+  let in_range = (read_index as usize) < slots && (write_index as usize) < slots;
+  let queued_slots = in_range.then(|| {
+    let mut queued = Vec::new();
+    let mut slot = bump_index(read_index, capacity);
+    while slot != write_index {
+      queued.push(slot as usize);
+      slot = bump_index(slot, capacity);
+    }
+    queued
+  });
+  QueueReport { read_index, write_index, frozen, queued_slots }
+}
+
+#[test]
+fn report_lists_queued_slots_across_the_wrap() {
+  let mut queue = crate::RingQueue::<u32>::new(3);
+  let mut unsync = queue.get_mut();
+  for item in 0 .. 3 { assert!(unsync.push(item).is_ok()) }
+  assert!(unsync.pop() == Some(0) && unsync.push(3).is_ok());
+  queue.freeze();
+  let report = unsafe { inspect(queue.backing_store(), 3) };
+  assert!(report.frozen && report.read_index == 0 && report.write_index == 4);
+  assert!(report.queued_slots == Some(vec![1, 2, 3]));
+  assert!(unsafe { inspect(queue.backing_store(), 2) }.queued_slots.is_none());
+  unsafe { queue.dispose() };
+}
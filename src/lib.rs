@@ -1,6 +1,88 @@
-#![feature(decl_macro)]
+#![feature(decl_macro, core_io_borrowed_buf, read_buf)]
+#![allow(clippy::needless_return)]
 
 
 mod ring_queue;
+mod split;
+mod static_queue;
+mod spill;
+mod credit;
+mod command;
+mod payload;
+mod soa;
+mod packed;
+mod clock;
+mod pace;
+mod replay;
+mod bip;
+mod seqlock;
+mod triple;
+mod task;
+mod mailbox;
+mod select;
+mod stage;
+mod expiry;
+mod dedup;
+mod rtt;
+mod histogram;
+mod group;
+mod token;
+mod wait;
+mod metrics;
+pub mod hooks;
+pub mod trace;
+pub mod alloc_hook;
+pub mod inspect;
+pub mod failpoints;
+pub mod pump;
+pub mod audio;
+#[cfg(feature = "tokio")]
+pub mod tokio_bridge;
+#[cfg(feature = "duplex-check")]
+pub mod duplex;
+#[cfg(any(test, feature = "registry"))]
+pub mod registry;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 
-pub use ring_queue::RingQueue;
+/// Fails the build if `condition`, a constant expression, is false.
+///
+/// Used at item level for the layout assumptions the pointer math relies on,
+/// and usable the same way for layouts built on top of the queue:
+///
+/// ```
+/// atomic_spsc_queue::static_assert!(size_of::<u64>() == 8, "u64 is 8 bytes");
+/// ```
+///
+/// ```compile_fail
+/// atomic_spsc_queue::static_assert!(size_of::<u64>() == 4);
+/// ```
+pub macro static_assert($condition:expr $(, $message:literal)?) {
+  const _: () = assert!($condition $(, $message)?);
+}
+
+pub use ring_queue::{RingQueue, EnqueueError, TryNewError, UnsyncRingQueue, SlotCopy, CacheLineReport};
+pub use split::{Producer, Consumer, PushError, PopError, DequeueGuard, InPlaceIter, Drain, ProducerHandoff, ConsumerThread};
+pub use static_queue::StaticRingQueue;
+pub use spill::SpillingProducer;
+pub use credit::{CreditedProducer, Credits, CreditError};
+pub use command::{CommandQueue, CommandProducer, CommandConsumer};
+pub use payload::{PayloadQueue, PayloadProducer, PayloadConsumer, PayloadGuard, PayloadStream};
+pub use soa::{SoaRingQueue, SoaProducer, SoaConsumer, Columns, Column};
+pub use packed::{PackedRingQueue, PackedProducer, PackedConsumer};
+pub use clock::{Clock, StdClock};
+pub use replay::ReplayConsumer;
+pub use bip::{BipBuffer, BipProducer, BipConsumer};
+pub use seqlock::{SeqLockCell, SeqLockWriter, SeqLockReader};
+pub use triple::{TripleBuffer, TripleBufferWriter, TripleBufferReader};
+pub use task::{Task, TaskQueue, TaskProducer, TaskConsumer};
+pub use mailbox::{Mailbox, MailboxSender, MailboxReceiver};
+pub use select::Select;
+pub use stage::{Stage, pipeline};
+pub use expiry::Expiring;
+pub use dedup::{DedupProducer, PushOutcome};
+pub use rtt::RttReport;
+pub use histogram::OccupancyHistogram;
+pub use group::RingGroup;
+pub use token::{TokenProducer, ProducerToken};
+pub use wait::AdaptiveWait;
@@ -0,0 +1,76 @@
+use crate::{Consumer, Producer, RingQueue, SpillingProducer};
+
+/// Actor mailbox with two lanes: a bounded data lane, and a control lane for
+/// messages such as shutdown or ping that is never refused. `recv` always
+/// prefers control messages.
+///
+/// Control messages that find their ring full wait in the sender, and move
+/// into the ring on the sender's next call, `flush` included.
+pub struct Mailbox<M> {
+  data: RingQueue<M>,
+  control: RingQueue<M>,
+}
+
+pub struct MailboxSender<M> {
+  data: Producer<M>,
+  control: SpillingProducer<M>,
+}
+
+pub struct MailboxReceiver<M> {
+  data: Consumer<M>,
+  control: Consumer<M>,
+}
+
+impl <M> Mailbox<M> {
+  pub fn new(data_capacity: usize, control_capacity: usize) -> Self {
+    Self { data: RingQueue::new(data_capacity), control: RingQueue::new(control_capacity) }
+  }
+  pub fn split(self) -> (MailboxSender<M>, MailboxReceiver<M>) {
+    let (data_producer, data_consumer) = self.data.split();
+    let (control_producer, control_consumer) = self.control.split();
+    (
+      MailboxSender { data: data_producer, control: control_producer.into_spilling() },
+      MailboxReceiver { data: data_consumer, control: control_consumer },
+    )
+  }
+}
+
+impl <M> MailboxSender<M> {
+  /// Queues a data message, handing it back if the data lane is full.
+  pub fn send(&mut self, message: M) -> Result<(), M> {
+    self.control.flush();
+    self.data.push(message)
+  }
+  pub fn send_control(&mut self, message: M) {
+    self.control.push(message);
+  }
+  /// Moves waiting control messages into their ring.
+  /// Returns true if none are left waiting.
+  pub fn flush(&mut self) -> bool {
+    self.control.flush()
+  }
+}
+
+impl <M> MailboxReceiver<M> {
+  /// The next control message, or else the next data message.
+  #[must_use]
+  pub fn recv(&mut self) -> Option<M> {
+    self.control.pop().or_else(|| self.data.pop())
+  }
+}
+
+#[test]
+fn control_messages_overtake_data() {
+  let (mut sender, mut receiver) = Mailbox::<&str>::new(2, 1).split();
+  sender.send("a").unwrap();
+  sender.send("b").unwrap();
+  assert!(sender.send("c").is_err());
+  sender.send_control("ping");
+  sender.send_control("shutdown");
+  assert!(receiver.recv() == Some("ping"));
+  assert!(receiver.recv() == Some("a"));
+  assert!(sender.flush());
+  assert!(receiver.recv() == Some("shutdown"));
+  assert!(receiver.recv() == Some("b"));
+  assert!(receiver.recv().is_none());
+}
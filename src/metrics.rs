@@ -0,0 +1,51 @@
+//! Per-queue counters and gauges reported through the `metrics` crate facade,
+//! enabled by the `metrics` feature; see `RingQueue::split_with_metrics`.
+//! Without the feature `QueueMetrics` is uninhabited, so the recording calls
+//! on the queue paths compile away.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QueueEvent {
+  Push,
+  Pop,
+  /// A push that found the ring full or frozen.
+  Reject,
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) struct QueueMetrics {
+  pushes: metrics::Counter,
+  pops: metrics::Counter,
+  rejections: metrics::Counter,
+  occupancy: metrics::Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl QueueMetrics {
+  pub(crate) fn new(name: &str) -> Self {
+    Self {
+      pushes: metrics::counter!("spsc_queue_pushes", "queue" => name.to_owned()),
+      pops: metrics::counter!("spsc_queue_pops", "queue" => name.to_owned()),
+      rejections: metrics::counter!("spsc_queue_rejections", "queue" => name.to_owned()),
+      occupancy: metrics::gauge!("spsc_queue_occupancy", "queue" => name.to_owned()),
+    }
+  }
+  pub(crate) fn record(&self, event: QueueEvent, count: usize, occupancy: impl FnOnce() -> usize) {
+    match event {
+      QueueEvent::Push => self.pushes.increment(count as u64),
+      QueueEvent::Pop => self.pops.increment(count as u64),
+      QueueEvent::Reject => self.rejections.increment(count as u64),
+    }
+    self.occupancy.set(occupancy() as f64);
+  }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) enum QueueMetrics {}
+
+#[cfg(not(feature = "metrics"))]
+impl QueueMetrics {
+  #[inline(always)]
+  pub(crate) fn record(&self, _event: QueueEvent, _count: usize, _occupancy: impl FnOnce() -> usize) {
+    match *self {}
+  }
+}
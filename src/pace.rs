@@ -0,0 +1,43 @@
+use std::time::Instant;
+
+/// Token bucket behind `Consumer::pop_paced`.
+pub(crate) struct TokenBucket {
+  tokens: f64,
+  last_refill: Instant,
+}
+
+impl TokenBucket {
+  pub(crate) fn full(burst: u32, now: Instant) -> Self {
+    Self { tokens: burst as f64, last_refill: now }
+  }
+  /// Adds the tokens accrued since the last refill, capped at `burst`.
+  pub(crate) fn refill(&mut self, rate: f64, burst: u32, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+    self.last_refill = now;
+  }
+  pub(crate) fn has_token(&self) -> bool {
+    self.tokens >= 1.0
+  }
+  pub(crate) fn spend(&mut self) {
+    self.tokens -= 1.0;
+  }
+}
+
+#[test]
+fn bucket_refills_at_rate_up_to_burst() {
+  use std::time::Duration;
+  let start = Instant::now();
+  let mut bucket = TokenBucket::full(2, start);
+  bucket.spend();
+  bucket.spend();
+  assert!(!bucket.has_token());
+  bucket.refill(4.0, 2, start + Duration::from_millis(100));
+  assert!(!bucket.has_token());
+  bucket.refill(4.0, 2, start + Duration::from_millis(250));
+  assert!(bucket.has_token());
+  bucket.refill(4.0, 2, start + Duration::from_secs(10));
+  bucket.spend();
+  bucket.spend();
+  assert!(!bucket.has_token());
+}
@@ -0,0 +1,107 @@
+use crate::{Consumer, Producer, RingQueue};
+use core::mem::MaybeUninit;
+
+/// One ring slot holding up to `K` small items.
+#[derive(Clone, Copy)]
+struct Pack<T: Copy, const K: usize> {
+  len: usize,
+  items: [MaybeUninit<T>; K],
+}
+
+/// Ring for items much smaller than a cache line: `K` items share a slot and
+/// the indices move once per slot instead of once per item.
+///
+/// A partly filled slot is only visible to the consumer after `flush`.
+pub struct PackedRingQueue<T: Copy, const K: usize> {
+  queue: RingQueue<Pack<T, K>>,
+}
+
+pub struct PackedProducer<T: Copy, const K: usize> {
+  producer: Producer<Pack<T, K>>,
+  /// items already written into the unpublished slot at the write position
+  filled: usize,
+}
+
+pub struct PackedConsumer<T: Copy, const K: usize> {
+  consumer: Consumer<Pack<T, K>>,
+  /// items already taken from the slot at the read position
+  taken: usize,
+}
+
+impl <T: Copy, const K: usize> PackedRingQueue<T, K> {
+  /// Room for at least `capacity` items.
+  pub fn new(capacity: usize) -> Self {
+    if K == 0 { panic!("Pack size must not be zero") }
+    Self { queue: RingQueue::new(capacity.div_ceil(K)) }
+  }
+  pub fn split(self) -> (PackedProducer<T, K>, PackedConsumer<T, K>) {
+    let (producer, consumer) = self.queue.split();
+    (PackedProducer { producer, filled: 0 }, PackedConsumer { consumer, taken: 0 })
+  }
+}
+
+impl <T: Copy, const K: usize> PackedProducer<T, K> {
+  /// Hands the item back if the ring is full. The item becomes visible once
+  /// its slot fills up or `flush` is called.
+  pub fn push(&mut self, item: T) -> Result<(), T> {
+    let filled = self.filled;
+    let Some(slot) = self.producer.write_chunk().first_mut() else { return Err(item) };
+    let pack = slot.as_mut_ptr();
+    unsafe { (&raw mut (*pack).items).cast::<MaybeUninit<T>>().add(filled).write(MaybeUninit::new(item)) };
+    self.filled += 1;
+    if self.filled == K { self.flush() }
+    return Ok(())
+  }
+  /// Publishes the partly filled slot, if any.
+  pub fn flush(&mut self) {
+    if self.filled == 0 { return }
+    let Some(slot) = self.producer.write_chunk().first_mut() else { unreachable!() };
+    unsafe { (&raw mut (*slot.as_mut_ptr()).len).write(self.filled) };
+    unsafe { self.producer.commit(1) };
+    self.filled = 0;
+  }
+}
+
+/// Publishes the partly filled slot, like `Producer` does with held-back items.
+impl <T: Copy, const K: usize> Drop for PackedProducer<T, K> {
+  fn drop(&mut self) {
+    self.flush()
+  }
+}
+
+impl <T: Copy, const K: usize> PackedConsumer<T, K> {
+  #[must_use]
+  pub fn pop(&mut self) -> Option<T> {
+    let pack = self.consumer.read_chunk().first()?;
+    let item = unsafe { pack.items[self.taken].assume_init() };
+    self.taken += 1;
+    if self.taken == pack.len {
+      self.consumer.release(1);
+      self.taken = 0;
+    }
+    return Some(item)
+  }
+}
+
+#[test]
+fn packed_items_arrive_in_order() {
+  let (mut producer, mut consumer) = PackedRingQueue::<u16, 4>::new(8).split();
+  for i in 0 .. 6 { assert!(producer.push(i).is_ok()) }
+  // the second slot is only half full and not published yet
+  let first = (0 .. 6).map_while(|_| consumer.pop()).collect::<Vec<_>>();
+  assert!(first == [0, 1, 2, 3]);
+  producer.flush();
+  assert!(consumer.pop() == Some(4) && consumer.pop() == Some(5) && consumer.pop().is_none());
+  for i in 0 .. 8 { assert!(producer.push(i).is_ok()) }
+  assert!(producer.push(8) == Err(8));
+  assert!((0 .. 8).all(|i| consumer.pop() == Some(i)));
+}
+
+#[test]
+fn dropping_the_producer_publishes_the_partial_slot() {
+  let (mut producer, mut consumer) = PackedRingQueue::<u16, 4>::new(8).split();
+  for i in 0 .. 3 { assert!(producer.push(i).is_ok()) }
+  assert!(consumer.pop().is_none());
+  drop(producer);
+  assert!((0 .. 3).all(|i| consumer.pop() == Some(i)) && consumer.pop().is_none());
+}
@@ -0,0 +1,184 @@
+use crate::{Consumer, Producer, RingQueue};
+use core::{cell::UnsafeCell, ops::Deref, sync::atomic::{AtomicU64, Ordering}};
+use std::{io::Read, sync::Arc};
+
+/// Location of a payload in the arena, as monotonic byte positions.
+struct Handle {
+  start: u64,
+  end: u64,
+}
+
+/// Byte ring holding the payloads; bytes are reclaimed in the order the
+/// handles pointing at them are consumed.
+struct Arena {
+  bytes: Box<[UnsafeCell<u8>]>,
+  /// everything before this position has been consumed
+  released: AtomicU64,
+}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+  fn size(&self) -> u64 {
+    self.bytes.len() as u64
+  }
+  fn region(&self, start: u64, end: u64) -> *mut [u8] {
+    let offset = (start % self.size()) as usize;
+    let first = UnsafeCell::raw_get(self.bytes[offset ..].as_ptr());
+    core::ptr::slice_from_raw_parts_mut(first, (end - start) as usize)
+  }
+}
+
+/// Queue for variable-size byte payloads: only small handles pass through the
+/// ring, while the payloads are written once into a companion arena and read
+/// from there in place.
+pub struct PayloadQueue {
+  handles: RingQueue<Handle>,
+  arena: Arc<Arena>,
+}
+
+pub struct PayloadProducer {
+  handles: Producer<Handle>,
+  arena: Arc<Arena>,
+  /// producer-private end of the last payload written
+  written: u64,
+}
+
+pub struct PayloadConsumer {
+  handles: Consumer<Handle>,
+  arena: Arc<Arena>,
+}
+
+impl PayloadQueue {
+  /// Room for `max_payloads` queued payloads totalling up to `arena_bytes`.
+  pub fn new(max_payloads: usize, arena_bytes: usize) -> Self {
+    if arena_bytes == 0 { panic!("Arena size must not be zero") }
+    let arena = Arena {
+      bytes: (0 .. arena_bytes).map(|_| UnsafeCell::new(0)).collect(),
+      released: AtomicU64::new(0),
+    };
+    Self { handles: RingQueue::new(max_payloads), arena: Arc::new(arena) }
+  }
+  pub fn split(self) -> (PayloadProducer, PayloadConsumer) {
+    let (handles_producer, handles_consumer) = self.handles.split();
+    (
+      PayloadProducer { handles: handles_producer, arena: self.arena.clone(), written: 0 },
+      PayloadConsumer { handles: handles_consumer, arena: self.arena },
+    )
+  }
+}
+
+impl PayloadProducer {
+  /// Reserves `len` contiguous bytes, lets `fill` write them in place and
+  /// queues the payload. Returns false, without calling `fill`, if either the
+  /// arena or the handle ring has no room.
+  #[must_use]
+  pub fn push_with(&mut self, len: usize, fill: impl FnOnce(&mut [u8])) -> bool {
+    let size = self.arena.size();
+    let len = len as u64;
+    let mut start = self.written;
+    // payloads never wrap; skip the arena's tail if this one doesn't fit there
+    if start % size + len > size { start = start.next_multiple_of(size) }
+    let end = start + len;
+    if len > size { return false }
+    // the new bytes must not reach around into what the consumer still holds
+    let released = self.arena.released.load(Ordering::Acquire);
+    if released != self.written && end - released > size { return false }
+    if self.handles.write_chunk().is_empty() { return false }
+    fill(unsafe { &mut *self.arena.region(start, end) });
+    if self.handles.push(Handle { start, end }).is_err() { return false }
+    self.written = end;
+    return true
+  }
+  #[must_use]
+  pub fn push(&mut self, payload: &[u8]) -> bool {
+    self.push_with(payload.len(), |region| region.copy_from_slice(payload))
+  }
+}
+
+impl PayloadConsumer {
+  /// Borrows the next payload in place; its bytes go back to the producer when the guard is dropped.
+  pub fn pop_ref(&mut self) -> Option<PayloadGuard<'_>> {
+    let handle = self.handles.pop()?;
+    Some(PayloadGuard { consumer: self, handle })
+  }
+  /// Reads the next payload incrementally through `Read`, so a large one can
+  /// be processed with a small buffer. Its bytes go back to the producer as a
+  /// whole when the stream is dropped, not piece by piece.
+  pub fn recv_stream(&mut self) -> Option<PayloadStream<'_>> {
+    let guard = self.pop_ref()?;
+    Some(PayloadStream { read: 0, guard })
+  }
+}
+
+pub struct PayloadStream<'a> {
+  guard: PayloadGuard<'a>,
+  /// bytes of the payload already read
+  read: usize,
+}
+impl PayloadStream<'_> {
+  /// Bytes not read yet.
+  pub fn remaining(&self) -> &[u8] {
+    &self.guard[self.read ..]
+  }
+}
+impl Read for PayloadStream<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let count = self.remaining().len().min(buf.len());
+    buf[.. count].copy_from_slice(&self.remaining()[.. count]);
+    self.read += count;
+    Ok(count)
+  }
+}
+
+pub struct PayloadGuard<'a> {
+  consumer: &'a mut PayloadConsumer,
+  handle: Handle,
+}
+impl Deref for PayloadGuard<'_> {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    unsafe { &*self.consumer.arena.region(self.handle.start, self.handle.end) }
+  }
+}
+impl Drop for PayloadGuard<'_> {
+  fn drop(&mut self) {
+    self.consumer.arena.released.store(self.handle.end, Ordering::Release);
+  }
+}
+
+#[test]
+fn payloads_wrap_inside_the_arena() {
+  let (mut producer, mut consumer) = PayloadQueue::new(4, 10).split();
+  assert!(producer.push(b"abcd"));
+  assert!(producer.push(b"efgh"));
+  // only 2 bytes left at the tail and none freed yet
+  assert!(!producer.push(b"ijk"));
+  assert!(&*consumer.pop_ref().unwrap() == b"abcd");
+  // the tail is skipped, so this lands at the start of the arena
+  assert!(producer.push(b"ijk"));
+  assert!(&*consumer.pop_ref().unwrap() == b"efgh");
+  assert!(&*consumer.pop_ref().unwrap() == b"ijk");
+  assert!(consumer.pop_ref().is_none());
+  assert!(!producer.push(&[0; 11]));
+  assert!(producer.push(&[1; 10]));
+}
+
+#[test]
+fn large_payload_is_read_in_pieces() {
+  let (mut producer, mut consumer) = PayloadQueue::new(2, 1000).split();
+  let payload = (0 .. 900u32).map(|i| i as u8).collect::<Vec<_>>();
+  assert!(producer.push(&payload));
+  let mut stream = consumer.recv_stream().unwrap();
+  let mut piece = [0; 64];
+  let mut received = Vec::new();
+  loop {
+    let count = stream.read(&mut piece).unwrap();
+    if count == 0 { break }
+    received.extend_from_slice(&piece[.. count]);
+    // the arena stays reserved until the whole stream is dropped
+    assert!(!producer.push(&[0; 200]));
+  }
+  drop(stream);
+  assert!(received == payload);
+  assert!(producer.push(&[0; 200]));
+}
@@ -0,0 +1,121 @@
+//! Loops moving bytes between the ring and `std::io` streams.
+//!
+//! Data is read straight into the ring's free slots and written straight out
+//! of its queued slots, with no intermediate buffer.
+
+use crate::{Consumer, Producer};
+use std::io::{self, BorrowedBuf, ErrorKind, IoSlice, IoSliceMut, Read, Write};
+
+impl Producer<u8> {
+  /// Copies `bufs` into the ring back to back, e.g. a header and a payload
+  /// held apart, and returns the number of bytes written. Stops at the first
+  /// buffer that doesn't fit completely.
+  pub fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> usize {
+    let mut total = 0;
+    for buf in bufs {
+      let count = self.push_slice(buf);
+      total += count;
+      if count < buf.len() { break }
+    }
+    return total
+  }
+}
+
+impl Consumer<u8> {
+  /// Fills `bufs` in order with queued bytes, copying each straight out of
+  /// the ring, and returns the number of bytes read. Stops at the first
+  /// buffer that can't be filled completely.
+  pub fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> usize {
+    let mut total = 0;
+    for buf in bufs {
+      let count = self.pop_slice(buf);
+      total += count;
+      if count < buf.len() { break }
+    }
+    return total
+  }
+}
+
+/// Reads `reader` to the end into the ring, waiting while the ring is full.
+/// Returns the number of bytes moved, or `BrokenPipe` if the consumer goes away.
+pub fn from_reader(mut reader: impl Read, mut producer: Producer<u8>) -> io::Result<u64> {
+  let mut total = 0;
+  loop {
+    let chunk = producer.write_chunk();
+    if chunk.is_empty() {
+      if producer.is_closed() { return Err(ErrorKind::BrokenPipe.into()) }
+      std::thread::yield_now();
+      continue
+    }
+    let mut buf = BorrowedBuf::from(chunk);
+    match reader.read_buf(buf.unfilled()) {
+      Ok(()) => (),
+      Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+      Err(error) => return Err(error),
+    }
+    let count = buf.len();
+    if count == 0 { return Ok(total) }
+    unsafe { producer.commit(count) };
+    total += count as u64;
+  }
+}
+
+/// Writes everything the producer sends into `writer`, until the producer is
+/// dropped and the ring is drained. Returns the number of bytes moved.
+pub fn to_writer(mut consumer: Consumer<u8>, mut writer: impl Write) -> io::Result<u64> {
+  let mut total = 0;
+  loop {
+    let producer_dropped = consumer.is_closed();
+    let chunk = consumer.read_chunk();
+    if chunk.is_empty() {
+      if producer_dropped { break }
+      std::thread::yield_now();
+      continue
+    }
+    let count = match writer.write(chunk) {
+      Ok(0) => return Err(ErrorKind::WriteZero.into()),
+      Ok(count) => count,
+      Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+      Err(error) => return Err(error),
+    };
+    consumer.release(count);
+    total += count as u64;
+  }
+  writer.flush()?;
+  return Ok(total)
+}
+
+#[test]
+fn bytes_survive_a_round_trip() {
+  let input = (0 .. 100_000u32).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+  let (producer, consumer) = crate::RingQueue::<u8>::new(1000).split();
+  let (sent, output) = std::thread::scope(|s| {
+    let reader = s.spawn(|| from_reader(&input[..], producer).unwrap());
+    let mut output = Vec::new();
+    to_writer(consumer, &mut output).unwrap();
+    (reader.join().unwrap(), output)
+  });
+  assert!(sent == input.len() as u64);
+  assert!(output == input);
+}
+
+#[test]
+fn read_vectored_spreads_bytes_over_the_buffers() {
+  let (mut producer, mut consumer) = crate::RingQueue::<u8>::new(8).split();
+  assert!(producer.push_slice(b"abcdef") == 6);
+  assert!(consumer.pop_slice(&mut [0; 4]) == 4);
+  // the queued bytes now wrap around the end of the ring
+  assert!(producer.push_slice(b"ghijkl") == 6);
+  let (mut head, mut body, mut tail) = ([0; 3], [0; 4], [0; 4]);
+  let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body), IoSliceMut::new(&mut tail)];
+  assert!(consumer.read_vectored(&mut bufs) == 8);
+  assert!(&head == b"efg" && &body == b"hijk" && tail[.. 1] == *b"l");
+}
+
+#[test]
+fn write_vectored_joins_the_buffers_in_the_ring() {
+  let (mut producer, mut consumer) = crate::RingQueue::<u8>::new(8).split();
+  assert!(producer.write_vectored(&[IoSlice::new(b"hdr:"), IoSlice::new(b"body"), IoSlice::new(b"!")]) == 8);
+  let mut out = [0; 8];
+  assert!(consumer.pop_slice(&mut out) == 8 && &out == b"hdr:body");
+}
@@ -0,0 +1,112 @@
+//! Process-wide registry of live queues, enabled by the `registry` feature.
+//!
+//! Every queue registers itself on construction and leaves on `dispose`.
+//! `live_queues` lists the queues that are still around, and `check_at_exit`
+//! reports the ones that were never disposed, or were disposed with items
+//! still in them, when the process exits.
+
+use crate::{ring_queue::occupancy_from_backing_store, RingQueue};
+use std::{backtrace::Backtrace, sync::{Arc, Mutex}};
+
+#[derive(Debug, Clone)]
+pub struct QueueInfo {
+  pub name: Option<String>,
+  pub capacity: usize,
+  pub occupancy: usize,
+  pub backtrace: Arc<Backtrace>,
+}
+
+struct Entry {
+  backing_store: usize,
+  name: Option<String>,
+  capacity: usize,
+  backtrace: Arc<Backtrace>,
+}
+impl Entry {
+  fn info(&self) -> QueueInfo {
+    let occupancy = unsafe { occupancy_from_backing_store(core::ptr::with_exposed_provenance_mut(self.backing_store), self.capacity) };
+    QueueInfo { name: self.name.clone(), capacity: self.capacity, occupancy, backtrace: self.backtrace.clone() }
+  }
+}
+
+static LIVE: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+static UNDRAINED: Mutex<Vec<QueueInfo>> = Mutex::new(Vec::new());
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn register(backing_store: *mut (), capacity: usize) {
+  let entry = Entry {
+    backing_store: backing_store.expose_provenance(),
+    name: None,
+    capacity,
+    backtrace: Arc::new(Backtrace::capture()),
+  };
+  lock(&LIVE).push(entry);
+}
+
+pub(crate) fn unregister(backing_store: *mut ()) {
+  let mut live = lock(&LIVE);
+  let Some(position) = live.iter().position(|entry| entry.backing_store == backing_store.addr()) else { return };
+  let entry = live.swap_remove(position);
+  drop(live);
+  let info = entry.info();
+  if info.occupancy != 0 { lock(&UNDRAINED).push(info) }
+}
+
+/// Attaches a name to `queue` in the registry's reports.
+pub fn set_name<T>(queue: &RingQueue<T>, name: impl Into<String>) {
+  let addr = queue.backing_store().addr();
+  if let Some(entry) = lock(&LIVE).iter_mut().find(|entry| entry.backing_store == addr) {
+    entry.name = Some(name.into());
+  }
+}
+
+/// Snapshot of all queues that have not been disposed yet.
+pub fn live_queues() -> Vec<QueueInfo> {
+  lock(&LIVE).iter().map(Entry::info).collect()
+}
+
+/// Queues that were disposed while still holding items.
+pub fn undrained_queues() -> Vec<QueueInfo> {
+  lock(&UNDRAINED).clone()
+}
+
+/// Prints every undisposed or undrained queue to stderr and returns how many there were.
+pub fn report_leaks() -> usize {
+  let live = live_queues();
+  let undrained = undrained_queues();
+  for info in &live {
+    eprintln!("queue {:?} (capacity {}, {} items) was never disposed, created at:\n{}", info.name, info.capacity, info.occupancy, info.backtrace);
+  }
+  for info in &undrained {
+    eprintln!("queue {:?} (capacity {}) was disposed with {} items, created at:\n{}", info.name, info.capacity, info.occupancy, info.backtrace);
+  }
+  return live.len() + undrained.len()
+}
+
+/// Runs `report_leaks` when the process exits normally.
+pub fn check_at_exit() {
+  unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> core::ffi::c_int;
+  }
+  extern "C" fn on_exit() {
+    report_leaks();
+  }
+  unsafe { atexit(on_exit) };
+}
+
+#[test]
+fn registry_tracks_live_and_undrained_queues() {
+  use core::mem::MaybeUninit;
+  let q = RingQueue::<u32>::new(8);
+  set_name(&q, "registry_test_queue");
+  assert!(q.enqueue_item(&MaybeUninit::new(1)));
+  let find = |list: Vec<QueueInfo>| list.into_iter().find(|info| info.name.as_deref() == Some("registry_test_queue"));
+  let info = find(live_queues()).unwrap();
+  assert!(info.capacity == 8 && info.occupancy == 1);
+  unsafe { q.dispose() };
+  assert!(find(live_queues()).is_none());
+  assert!(find(undrained_queues()).unwrap().occupancy == 1);
+}
@@ -0,0 +1,63 @@
+use crate::Consumer;
+
+/// Consumer that keeps the last `window` items it has read in the ring, so
+/// they can be read again after `rewind`, e.g. to retry processing that
+/// failed downstream. Items are read in place and only dropped and handed
+/// back to the producer once they fall out of the window, which leaves the
+/// producer `window + 1` slots short of the full capacity.
+pub struct ReplayConsumer<T> {
+  consumer: Consumer<T>,
+  window: usize,
+  /// items read but still held in the ring
+  retained: usize,
+}
+
+impl <T> Consumer<T> {
+  pub fn into_replay(self, window: usize) -> ReplayConsumer<T> {
+    ReplayConsumer { consumer: self, window, retained: 0 }
+  }
+}
+
+impl <T> ReplayConsumer<T> {
+  /// Borrows the next item. The previously returned item stays retained
+  /// while the oldest ones beyond the window are released.
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> Option<&T> {
+    self.consumer.peek_nth(self.retained)?;
+    while self.retained > self.window {
+      drop(self.consumer.pop());
+      self.retained -= 1;
+    }
+    self.retained += 1;
+    self.consumer.peek_nth(self.retained - 1)
+  }
+  /// Steps back `count` items, so that `next` returns them again.
+  pub fn rewind(&mut self, count: usize) {
+    assert!(count <= self.retained, "Rewound past the retained window");
+    self.retained -= count;
+  }
+  /// Number of items that `rewind` can step back over.
+  pub fn retained(&self) -> usize {
+    self.retained
+  }
+  /// Gives the consumer back; retained items are still queued and will be popped again.
+  pub fn into_inner(self) -> Consumer<T> {
+    self.consumer
+  }
+}
+
+#[test]
+fn rewound_items_are_read_again() {
+  let (mut producer, consumer) = crate::RingQueue::<u32>::new(6).split();
+  let mut consumer = consumer.into_replay(2);
+  for i in 0 .. 6 { producer.push(i).unwrap() }
+  assert!((0 .. 4).all(|i| consumer.next() == Some(&i)));
+  // 0 was released, 1 and 2 are in the window, 3 is the current item
+  assert!(consumer.retained() == 3);
+  assert!(producer.push(6).is_ok() && producer.push(7).is_err());
+  consumer.rewind(3);
+  assert!((1 .. 7).all(|i| consumer.next() == Some(&i)));
+  assert!(consumer.next().is_none());
+  let mut consumer = consumer.into_inner();
+  assert!(consumer.pop() == Some(4));
+}
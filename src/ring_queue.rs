@@ -1,58 +1,722 @@
-use core::{alloc::Layout, marker::PhantomData, mem::MaybeUninit, ptr::copy_nonoverlapping, sync::atomic::{fence, AtomicU32, Ordering}};
+use crate::{alloc_hook::{notify, AllocEvent}, clock::Clock, failpoints::{fail_point, Failpoint}, hooks::{hook, HookPoint}, trace::{record, TraceOp}};
+use core::{alloc::Layout, marker::PhantomData, mem::MaybeUninit, ptr::copy_nonoverlapping, sync::atomic::{AtomicU32, Ordering}};
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+use core::sync::atomic::AtomicU8;
 
 #[repr(C)]
-struct Metadata {
-  read_index: AtomicU32,
-  write_index: AtomicU32
+pub(crate) struct Metadata {
+  pub(crate) read_index: AtomicU32,
+  pub(crate) write_index: AtomicU32,
+  pub(crate) flags: AtomicU32,
 }
 
+impl Metadata {
+  /// Index state of an empty ring with `capacity`.
+  pub(crate) const fn initial(capacity:usize) -> Self {
+    let indexing_adjusted_capacity = indexing_adjusted_capacity(capacity);
+    Metadata {
+      read_index: AtomicU32::new((indexing_adjusted_capacity - 1) as _),
+      write_index: AtomicU32::new(0),
+      flags: AtomicU32::new(0),
+    }
+  }
+}
+
+// The metadata sits right before the first slot, at `midpoint - size` from an
+// origin aligned for both; that address is aligned only if the size is a
+// multiple of the alignment.
+crate::static_assert!(size_of::<Metadata>().is_multiple_of(align_of::<Metadata>()), "Metadata size must be a multiple of its alignment");
+
+pub(crate) const FROZEN_FLAG: u32 = 1;
+
+/// Line size assumed by `cache_line_report`; 64 bytes on x86_64 and most ARM cores.
+const CACHE_LINE: usize = 64;
+
+/// Result of `RingQueue::cache_line_report`; all false means no false sharing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheLineReport {
+  /// The read and write indices, written by opposite sides.
+  pub indices_share_line: bool,
+  /// The end of the metadata and the first slot.
+  pub metadata_shares_first_slot: bool,
+  /// The first and last slots, which the two sides touch around the wrap.
+  pub first_slot_shares_last_slot: bool,
+}
+
+/// Why an item was not enqueued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueError {
+  Full,
+  /// The queue is frozen, see `RingQueue::freeze`.
+  Paused,
+}
+
+/// Why `RingQueue::try_new` couldn't build a queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryNewError {
+  /// The backing store for the requested capacity would exceed `isize::MAX` bytes.
+  CapacityOverflow,
+  /// The capacity is zero, or too large for the 32-bit indices.
+  InvalidCapacity,
+  /// The allocator returned null.
+  AllocFailed,
+}
 
 pub struct RingQueue<T> {
   raw_queue: RingQueueRaw,
+  #[cfg(any(debug_assertions, feature = "paranoid"))]
+  roles: RoleTracker,
   _phantom: PhantomData<T>
 }
 impl <T> RingQueue<T> {
   pub fn new(capacity:usize) -> Self {
-    Self { raw_queue: new_ring_queue(Layout::new::<Metadata>(), Layout::new::<T>(), capacity), _phantom: PhantomData }
+    Self::from_raw_queue(new_ring_queue(Layout::new::<Metadata>(), Layout::new::<T>(), capacity))
   }
+  /// Like `new`, but reports an invalid capacity, one whose backing store
+  /// doesn't fit the address space, or an allocation failure, instead of
+  /// panicking or aborting, for capacities that come from configuration or
+  /// the network.
+  pub fn try_new(capacity:usize) -> Result<Self, TryNewError> {
+    Ok(Self::from_raw_queue(try_new_ring_queue(Layout::new::<Metadata>(), Layout::new::<T>(), capacity)?))
+  }
+  fn from_raw_queue(raw_queue: RingQueueRaw) -> Self {
+    let queue = Self {
+      raw_queue,
+      #[cfg(any(debug_assertions, feature = "paranoid"))]
+      roles: RoleTracker::default(),
+      _phantom: PhantomData
+    };
+    #[cfg(feature = "false-sharing-check")]
+    queue.warn_false_sharing();
+    return queue
+  }
+  /// Like `new`, but moves items into and out of the slots with `slot_copy`
+  /// instead of `copy_nonoverlapping`.
+  ///
+  /// # Safety
+  ///
+  /// Both routines of `slot_copy` must copy exactly `len` bytes from `src` to `dst`.
+  pub unsafe fn with_slot_copy(capacity:usize, slot_copy: SlotCopy) -> Self {
+    let mut queue = Self::new(capacity);
+    queue.raw_queue.slot_copy = Some(slot_copy);
+    return queue
+  }
+  /// Layout of the memory a queue of `capacity` items occupies, for use with `from_memory`.
+  pub fn memory_layout(capacity:usize) -> Layout {
+    check_capacity(capacity);
+    backing_store_layout(Layout::new::<Metadata>(), Layout::new::<T>(), capacity).0
+  }
+  /// Builds the queue inside caller-provided memory, e.g. a mapped device
+  /// buffer, moving items with `slot_copy`. The metadata sits at the start of
+  /// the region, followed by the slots. `dispose` leaves the memory alone.
+  ///
+  /// # Safety
+  ///
+  /// `memory` must fit `memory_layout(capacity)`, stay valid and unused by
+  /// anything else until the queue is disposed, and support atomic operations
+  /// on the metadata part.
+  pub unsafe fn from_memory(memory: core::ptr::NonNull<u8>, capacity:usize, slot_copy: SlotCopy) -> Self {
+    unsafe { Self::in_memory(memory, capacity, Some(slot_copy)) }
+  }
+  /// `from_memory` for ordinary memory, where the default copy applies.
+  ///
+  /// # Safety
+  ///
+  /// As for `from_memory`.
+  pub(crate) unsafe fn in_memory(memory: core::ptr::NonNull<u8>, capacity:usize, slot_copy: Option<SlotCopy>) -> Self {
+    check_capacity(capacity);
+    let (layout, midpoint) = backing_store_layout(Layout::new::<Metadata>(), Layout::new::<T>(), capacity);
+    assert!(memory.as_ptr().addr().is_multiple_of(layout.align()), "Memory is not aligned to {}", layout.align());
+    let mid_ptr = memory.as_ptr().map_addr(|addr| addr + midpoint).cast::<()>();
+    let queue = Self {
+      raw_queue: init_ring_queue(mid_ptr, Layout::new::<Metadata>(), capacity, false, slot_copy),
+      #[cfg(any(debug_assertions, feature = "paranoid"))]
+      roles: RoleTracker::default(),
+      _phantom: PhantomData
+    };
+    #[cfg(feature = "false-sharing-check")]
+    queue.warn_false_sharing();
+    return queue
+  }
+  /// Which of the hot locations of the queue share a cache line; each pair
+  /// that does is written by one side while the other keeps reading it.
+  pub fn cache_line_report(&self) -> CacheLineReport {
+    let line = |addr: usize| addr / CACHE_LINE;
+    let mtd = metadata_ref(&self.raw_queue, Layout::new::<Metadata>());
+    let item_size = size_of::<T>().max(1);
+    let first_slot = slot_ptr(&self.raw_queue, Layout::new::<T>(), 0).addr();
+    let last_slot = slot_ptr(&self.raw_queue, Layout::new::<T>(), indexing_adjusted_capacity(self.raw_queue.capacity) - 1).addr();
+    CacheLineReport {
+      indices_share_line: line((&raw const mtd.read_index).addr()) == line((&raw const mtd.write_index).addr()),
+      metadata_shares_first_slot: line(self.raw_queue.backing_store.addr() - 1) == line(first_slot),
+      first_slot_shares_last_slot: line(first_slot + item_size - 1) == line(last_slot),
+    }
+  }
+  #[cfg(feature = "false-sharing-check")]
+  fn warn_false_sharing(&self) {
+    let report = self.cache_line_report();
+    if report != CacheLineReport::default() {
+      eprintln!("warning: ring queue at {:p} shares cache lines between hot locations: {:?}", self.raw_queue.backing_store, report);
+    }
+  }
+  #[must_use]
   pub fn enqueue_item(&self, item: &MaybeUninit<T>) -> bool {
-    enqueue_item_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), item.as_ptr().cast())
+    self.try_enqueue_item(item).is_ok()
   }
+  pub fn try_enqueue_item(&self, item: &MaybeUninit<T>) -> Result<(), EnqueueError> {
+    #[cfg(any(debug_assertions, feature = "paranoid"))]
+    let _role = RoleGuard::enter(&self.roles.producer, "producer");
+    if self.is_frozen() { return Err(EnqueueError::Paused) }
+    let ok = enqueue_item_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), item.as_ptr().cast());
+    if !ok { return Err(EnqueueError::Full) }
+    return Ok(())
+  }
+  #[must_use]
   pub fn dequeue_item(&self, item: &mut MaybeUninit<T>) -> bool {
+    #[cfg(any(debug_assertions, feature = "paranoid"))]
+    let _role = RoleGuard::enter(&self.roles.consumer, "consumer");
     dequeue_item_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), item.as_mut_ptr().cast())
   }
+  /// Spins until the item is enqueued or `clock` reaches `deadline`.
+  /// Returns false if the deadline passed while the queue stayed full or frozen.
+  #[must_use]
+  pub fn enqueue_item_deadline<C: Clock>(&self, item: &MaybeUninit<T>, clock: &C, deadline: C::Instant) -> bool {
+    loop {
+      if self.enqueue_item(item) { return true }
+      if clock.now() >= deadline { return false }
+      core::hint::spin_loop();
+    }
+  }
+  /// Makes subsequent pushes fail with `EnqueueError::Paused` until `resume` is called,
+  /// while the consumer keeps draining. A push that had already passed the check when
+  /// `freeze` was called may still publish its item; once the producer has observed
+  /// `Paused`, the written part of the ring stays unchanged until `resume`.
+  pub fn freeze(&self) {
+    metadata_ref(&self.raw_queue, Layout::new::<Metadata>()).flags.fetch_or(FROZEN_FLAG, Ordering::AcqRel);
+  }
+  pub fn resume(&self) {
+    metadata_ref(&self.raw_queue, Layout::new::<Metadata>()).flags.fetch_and(!FROZEN_FLAG, Ordering::AcqRel);
+  }
+  pub fn is_frozen(&self) -> bool {
+    metadata_ref(&self.raw_queue, Layout::new::<Metadata>()).flags.load(Ordering::Acquire) & FROZEN_FLAG != 0
+  }
+  /// Single-threaded access for pre-filling or draining the queue without atomics.
+  pub fn get_mut(&mut self) -> UnsyncRingQueue<'_, T> {
+    UnsyncRingQueue { queue: self }
+  }
+  /// A queue of `capacity` already holding the items of `iter`, e.g. a free
+  /// list of buffers that starts out full. Panics if `iter` yields more than
+  /// `capacity` items.
+  pub fn from_iter_with_capacity(iter: impl IntoIterator<Item = T>, capacity:usize) -> Self {
+    let mut queue = Self::new(capacity);
+    let mut unsync = queue.get_mut();
+    for item in iter {
+      if unsync.push(item).is_err() { panic!("Iterator yielded more than {} items", capacity) }
+    }
+    return queue
+  }
+  /// The queued items in order; frees the ring.
+  pub fn into_vec(mut self) -> Vec<T> {
+    let mut unsync = self.get_mut();
+    let items = core::iter::from_fn(|| unsync.pop()).collect();
+    unsafe { self.dispose() };
+    return items
+  }
+  /// Moves the queued items, in order, into a new allocation for
+  /// `new_capacity` items and frees the old one.
+  ///
+  /// Panics if `new_capacity` is larger than the current capacity or smaller
+  /// than the number of queued items, or if the queue lives in memory from
+  /// `from_memory`.
+  pub fn shrink(&mut self, new_capacity:usize) {
+    assert!(self.raw_queue.owns_memory, "Queue in caller memory can't be reallocated");
+    assert!(new_capacity <= self.raw_queue.capacity, "Shrinking to a larger capacity");
+    assert!(self.occupancy() <= new_capacity, "More than {} items are queued", new_capacity);
+    let mut shrunk = Self::new(new_capacity);
+    shrunk.raw_queue.slot_copy = self.raw_queue.slot_copy;
+    if self.is_frozen() { shrunk.freeze() }
+    let mut from = self.get_mut();
+    let mut to = shrunk.get_mut();
+    while let Some(item) = from.pop() {
+      if to.push(item).is_err() { unreachable!() }
+    }
+    unsafe { core::mem::replace(self, shrunk).dispose() };
+  }
+  /// Like `new`, and warms the queue before handing it out, see `warm`.
+  pub fn new_warm(capacity:usize) -> Self {
+    let mut queue = Self::new(capacity);
+    queue.warm();
+    return queue
+  }
+  /// Reads and writes back one byte of every cache line of the backing
+  /// store, leaving the contents unchanged, so that the pages are faulted
+  /// in, their TLB entries loaded and the lines cached before the first
+  /// real push and pop, which otherwise pay for all of that.
+  pub fn warm(&mut self) {
+    const LINE: usize = 64;
+    let origin_ptr = mid_to_origin_ptr(self.raw_queue.backing_store, Layout::new::<Metadata>(), Layout::new::<T>());
+    let (layout, _) = backing_store_layout(Layout::new::<Metadata>(), Layout::new::<T>(), self.raw_queue.capacity);
+    for offset in (0 .. layout.size()).step_by(LINE) {
+      // `MaybeUninit`, as free slots are uninitialized
+      let byte = origin_ptr.map_addr(|addr| addr + offset).cast::<MaybeUninit<u8>>();
+      unsafe { byte.write_volatile(byte.read_volatile()) };
+    }
+  }
+  /// Locks the backing store into RAM with `mlock`, which also faults every
+  /// page in, so a real-time thread never takes a page fault on a slot.
+  /// Fails, leaving the queue usable but unlocked, if the `RLIMIT_MEMLOCK`
+  /// limit or permissions don't allow it. A queue reallocated by `shrink`
+  /// has to be locked again.
+  #[cfg(feature = "mlock")]
+  pub fn lock_memory(&mut self) -> std::io::Result<()> {
+    if self.raw_queue.locked { return Ok(()) }
+    let origin_ptr = mid_to_origin_ptr(self.raw_queue.backing_store, Layout::new::<Metadata>(), Layout::new::<T>());
+    let (layout, _) = backing_store_layout(Layout::new::<Metadata>(), Layout::new::<T>(), self.raw_queue.capacity);
+    if unsafe { libc::mlock(origin_ptr.cast(), layout.size()) } != 0 { return Err(std::io::Error::last_os_error()) }
+    self.raw_queue.locked = true;
+    return Ok(())
+  }
+  /// Drops the queued items and puts the indices back where `new` left them,
+  /// keeping the allocation.
+  pub fn reset(&mut self) {
+    let mut unsync = self.get_mut();
+    while unsync.pop().is_some() {}
+    *metadata_mut(&mut self.raw_queue, Layout::new::<Metadata>()) = Metadata::initial(self.raw_queue.capacity);
+    #[cfg(any(debug_assertions, feature = "paranoid"))]
+    if let Some(slot_states) = &mut self.raw_queue.slot_states {
+      for state in slot_states.iter_mut() { *state.get_mut() = SlotState::Empty as u8 }
+    }
+  }
+  /// Pointer to the next item without dequeuing it.
+  /// The item stays in place until `release_item`.
+  pub(crate) fn peek_item(&self) -> Option<core::ptr::NonNull<T>> {
+    let slot = peek_item_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>());
+    core::ptr::NonNull::new(slot.cast::<T>())
+  }
+  /// Pointer to the queued item `n` places after the next one, left in place.
+  pub(crate) fn peek_nth(&self, n:usize) -> Option<core::ptr::NonNull<T>> {
+    let slot = peek_nth_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), n);
+    core::ptr::NonNull::new(slot.cast::<T>())
+  }
+  /// # Safety
+  ///
+  /// Must follow a successful `peek_item`, and the peeked item must have been moved out or dropped.
+  pub(crate) unsafe fn release_item(&self) {
+    release_item_prim(&self.raw_queue, Layout::new::<Metadata>());
+  }
+  /// Free slots that can be filled in place, up to the wrap point. Empty while frozen.
+  pub(crate) fn writable_chunk(&self) -> *mut [MaybeUninit<T>] {
+    if self.is_frozen() { return core::ptr::slice_from_raw_parts_mut(core::ptr::NonNull::dangling().as_ptr(), 0) }
+    let (first_slot, len) = writable_chunk_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>());
+    core::ptr::slice_from_raw_parts_mut(first_slot.cast(), len)
+  }
+  /// Moves `item` into `slot` with the queue's `SlotCopy`, like `enqueue_item` does.
+  ///
+  /// # Safety
+  ///
+  /// `slot` must be a free slot from the last `writable_chunk`.
+  #[inline]
+  pub(crate) unsafe fn write_slot(&self, slot: *mut MaybeUninit<T>, item: T) {
+    let item = MaybeUninit::new(item);
+    copy_into_slot(&self.raw_queue, item.as_ptr().cast(), slot.cast(), size_of::<T>());
+  }
+  /// # Safety
+  ///
+  /// The first `count` slots of the last `writable_chunk` must be initialized.
+  pub(crate) unsafe fn commit_write(&self, count:usize) {
+    commit_write_prim(&self.raw_queue, Layout::new::<Metadata>(), count);
+  }
+  /// Queued items that can be read in place, up to the wrap point.
+  pub(crate) fn readable_chunk(&self) -> *mut [T] {
+    let (first_slot, len) = readable_chunk_prim(&self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>());
+    core::ptr::slice_from_raw_parts_mut(first_slot.cast(), len)
+  }
+  /// # Safety
+  ///
+  /// `count` must not exceed the last `readable_chunk`, and those items must have been moved out or dropped.
+  pub(crate) unsafe fn release_read(&self, count:usize) {
+    release_read_prim(&self.raw_queue, Layout::new::<Metadata>(), count);
+  }
+  /// Items currently queued; only a snapshot while the other side is active.
+  pub(crate) fn occupancy(&self) -> usize {
+    unsafe { occupancy_from_backing_store(self.raw_queue.backing_store, self.raw_queue.capacity) }
+  }
+  pub(crate) fn capacity(&self) -> usize {
+    self.raw_queue.capacity
+  }
+  #[cfg(any(test, feature = "registry"))]
+  pub(crate) fn backing_store(&self) -> *mut () {
+    self.raw_queue.backing_store
+  }
+  /// Gives up the queue as a pointer to its first slot, with the metadata
+  /// right before it, and its capacity, e.g. to keep it in an FFI-visible
+  /// struct. Queued items stay in place; `from_raw_parts` takes the queue back.
+  ///
+  /// Panics for queues from `with_slot_copy` or `from_memory`, or locked by
+  /// `lock_memory`, as the parts don't carry that state.
+  pub fn into_raw_parts(self) -> (core::ptr::NonNull<u8>, usize) {
+    assert!(self.raw_queue.owns_memory && self.raw_queue.slot_copy.is_none(), "Only queues from `new` have raw parts");
+    #[cfg(feature = "mlock")]
+    assert!(!self.raw_queue.locked, "Locked queue has no raw parts");
+    let backing_store = core::ptr::NonNull::new(self.raw_queue.backing_store.cast::<u8>()).unwrap();
+    return (backing_store, self.raw_queue.capacity)
+  }
+  /// Rebuilds a queue from `into_raw_parts`. Debug builds don't track slot
+  /// states for it, as those aren't part of the raw parts.
+  ///
+  /// # Safety
+  ///
+  /// The parts must come from `into_raw_parts` of a `RingQueue<T>` with the
+  /// same `T`, and be turned back into a queue at most once.
+  pub unsafe fn from_raw_parts(backing_store: core::ptr::NonNull<u8>, capacity:usize) -> Self {
+    Self {
+      raw_queue: RingQueueRaw {
+        backing_store: backing_store.as_ptr().cast(),
+        capacity,
+        owns_memory: true,
+        slot_copy: None,
+        #[cfg(feature = "mlock")]
+        locked: false,
+        #[cfg(any(debug_assertions, feature = "paranoid"))]
+        slot_states: None,
+      },
+      #[cfg(any(debug_assertions, feature = "paranoid"))]
+      roles: RoleTracker::default(),
+      _phantom: PhantomData
+    }
+  }
   /// ensure to drain the q
+  ///
+  /// # Safety
+  ///
+  /// Items still in the queue are not dropped, and no other reference to the queue may be alive.
   pub unsafe fn dispose(self) {
     destroy(self.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>());
   }
 }
 
-struct RingQueueRaw {
-  backing_store: *mut (),
-  capacity: usize,
+/// Routines that move item bytes into and out of slots, for rings placed in
+/// memory where a plain `memcpy` is the wrong tool (write-combined or
+/// device-visible mappings), or for hardware where another copy loop, or a DMA
+/// engine, beats it. Each is called as `(src, dst, len)`. They use the
+/// C ABI, which cannot unwind, so `push` and `pop` stay free of panic paths.
+///
+/// The in-place APIs (`write_chunk`, `read_chunk`, `pop_ref`) access slots
+/// directly and bypass these.
+#[derive(Debug, Clone, Copy)]
+pub struct SlotCopy {
+  pub into_slot: unsafe extern "C" fn(*const u8, *mut u8, usize),
+  pub from_slot: unsafe extern "C" fn(*const u8, *mut u8, usize),
+}
+
+impl SlotCopy {
+  /// Volatile word-sized copies, which the compiler may neither elide, merge
+  /// nor reorder; suited to uncached and write-combined device memory.
+  pub const VOLATILE: SlotCopy = SlotCopy { into_slot: volatile_copy, from_slot: volatile_copy };
+  /// A single `rep movsb`, which is the fastest copy on CPUs with ERMSB/FSRM
+  /// for the mid-sized items where `memcpy`'s size dispatch dominates.
+  #[cfg(target_arch = "x86_64")]
+  pub const REP_MOVSB: SlotCopy = SlotCopy { into_slot: rep_movsb, from_slot: rep_movsb };
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe extern "C" fn rep_movsb(src: *const u8, dst: *mut u8, len: usize) {
+  unsafe {
+    core::arch::asm!(
+      "rep movsb",
+      inout("rcx") len => _,
+      inout("rsi") src => _,
+      inout("rdi") dst => _,
+      options(nostack, preserves_flags),
+    )
+  };
+}
+
+pub(crate) unsafe extern "C" fn volatile_copy(src: *const u8, dst: *mut u8, len: usize) {
+  let words = if src.addr().is_multiple_of(align_of::<usize>()) && dst.addr().is_multiple_of(align_of::<usize>()) { len / size_of::<usize>() } else { 0 };
+  for i in 0 .. words {
+    unsafe { dst.cast::<usize>().add(i).write_volatile(src.cast::<usize>().add(i).read_volatile()) };
+  }
+  for i in words * size_of::<usize>() .. len {
+    unsafe { dst.add(i).write_volatile(src.add(i).read_volatile()) };
+  }
+}
+
+/// Which sides of the queue are in the middle of an operation. Two threads
+/// entering the same side at once means the single-producer/single-consumer
+/// contract of the `&RingQueue` API is being broken.
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+#[derive(Default)]
+struct RoleTracker {
+  producer: core::sync::atomic::AtomicBool,
+  consumer: core::sync::atomic::AtomicBool,
+}
+
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+struct RoleGuard<'a>(&'a core::sync::atomic::AtomicBool);
+
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+impl <'a> RoleGuard<'a> {
+  fn enter(flag: &'a core::sync::atomic::AtomicBool, role: &str) -> Self {
+    if flag.swap(true, Ordering::Acquire) {
+      crate::trace::dump();
+      panic!("Two threads act as the {} of the queue at once", role)
+    }
+    RoleGuard(flag)
+  }
+}
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+impl Drop for RoleGuard<'_> {
+  fn drop(&mut self) {
+    self.0.store(false, Ordering::Release);
+  }
+}
+
+/// A full queue holding the items of `items`, with just enough capacity for them.
+impl <T> From<Vec<T>> for RingQueue<T> {
+  fn from(items: Vec<T>) -> Self {
+    let capacity = items.len().max(1);
+    Self::from_iter_with_capacity(items, capacity)
+  }
+}
+
+/// Uniquely borrowed queue whose operations use plain index loads and stores,
+/// see `RingQueue::get_mut`.
+pub struct UnsyncRingQueue<'a, T> {
+  queue: &'a mut RingQueue<T>,
+}
+impl <T> UnsyncRingQueue<'_, T> {
+  /// Hands the item back if the queue is full.
+  pub fn push(&mut self, item: T) -> Result<(), T> {
+    let item = MaybeUninit::new(item);
+    let ok = enqueue_item_unsync_prim(&mut self.queue.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), item.as_ptr().cast());
+    if !ok { return Err(unsafe { item.assume_init() }) }
+    return Ok(())
+  }
+  #[must_use]
+  pub fn pop(&mut self) -> Option<T> {
+    let mut item = MaybeUninit::<T>::uninit();
+    let ok = dequeue_item_unsync_prim(&mut self.queue.raw_queue, Layout::new::<Metadata>(), Layout::new::<T>(), item.as_mut_ptr().cast());
+    if !ok { return None }
+    return Some(unsafe { item.assume_init() })
+  }
+}
+
+pub(crate) struct RingQueueRaw {
+  pub(crate) backing_store: *mut (),
+  pub(crate) capacity: usize,
+  /// false when the caller provided the memory, which is then not freed on dispose
+  pub(crate) owns_memory: bool,
+  /// `None` copies with `copy_nonoverlapping`
+  pub(crate) slot_copy: Option<SlotCopy>,
+  /// set by `lock_memory`, so the pages are unlocked before they are freed
+  #[cfg(feature = "mlock")]
+  pub(crate) locked: bool,
+  /// `None` for rings whose storage is not allocated by `new_ring_queue`
+  #[cfg(any(debug_assertions, feature = "paranoid"))]
+  pub(crate) slot_states: Option<Box<[AtomicU8]>>,
 }
 unsafe impl Sync for RingQueueRaw {}
 
-fn indexing_adjusted_capacity(capacity:usize) -> usize {
+/// Per-slot state tracked in debug builds to catch index-math corruption
+/// at the operation that causes it rather than as garbled data later.
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum SlotState {
+  Empty,
+  Written,
+  Consumed,
+}
+
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+impl SlotState {
+  fn from_u8(value: u8) -> Self {
+    match value {
+      0 => SlotState::Empty,
+      1 => SlotState::Written,
+      _ => SlotState::Consumed,
+    }
+  }
+}
+
+#[inline(always)]
+fn tag_slot_written(queue: &RingQueueRaw, index: u32) {
+  #[cfg(any(debug_assertions, feature = "paranoid"))]
+  if let Some(slot_states) = &queue.slot_states {
+    let state = SlotState::from_u8(slot_states[index as usize].load(Ordering::Relaxed));
+    if state == SlotState::Written {
+      crate::trace::dump();
+      panic!("Slot {} is overwritten before it was consumed", index)
+    }
+    slot_states[index as usize].store(SlotState::Written as u8, Ordering::Relaxed);
+  }
+  #[cfg(not(any(debug_assertions, feature = "paranoid")))] {
+    let _ = (queue, index);
+  }
+}
+
+#[inline(always)]
+fn tag_slot_consumed(queue: &RingQueueRaw, index: u32) {
+  #[cfg(any(debug_assertions, feature = "paranoid"))]
+  if let Some(slot_states) = &queue.slot_states {
+    let state = SlotState::from_u8(slot_states[index as usize].load(Ordering::Relaxed));
+    if state != SlotState::Written {
+      crate::trace::dump();
+      panic!("Slot {} is read while {:?}", index, state)
+    }
+    slot_states[index as usize].store(SlotState::Consumed as u8, Ordering::Relaxed);
+  }
+  #[cfg(not(any(debug_assertions, feature = "paranoid")))] {
+    let _ = (queue, index);
+  }
+}
+
+pub(crate) const fn indexing_adjusted_capacity(capacity:usize) -> usize {
   capacity + 2
 }
 
+/// Indices stay below `indexing_adjusted_capacity` rather than counting up,
+/// so they never wrap their integer type; this only has to keep that bound
+/// itself representable.
+pub(crate) const fn check_capacity(capacity:usize) {
+  if let Some(problem) = capacity_problem(capacity) { panic!("{}", problem) }
+}
+
+/// What is wrong with `capacity`, if anything; see `check_capacity`.
+pub(crate) const fn capacity_problem(capacity:usize) -> Option<&'static str> {
+  if capacity == 0 { return Some("Capacity must not be zero") }
+  if capacity > u32::MAX as usize - 2 { return Some("Capacity must fit the 32-bit indices") }
+  return None
+}
+
 #[inline(always)]
-fn alloc_ring_queue_backing_store(
+pub(crate) fn bump_index(index:u32, capacity:usize) -> u32 {
+  let bumped_index = index + 1;
+  bumped_index * (!(bumped_index == (indexing_adjusted_capacity(capacity) as u32)) as u32)
+}
+
+/// Layout of the whole backing store, and the offset of the first slot in it.
+///
+/// The origin is aligned for both the metadata and the items and the first
+/// slot sits at a multiple of the item alignment past it, so every slot is
+/// aligned whatever the item alignment; the only layouts that can't be
+/// honored are those whose size overflows, with or without the padding.
+fn try_backing_store_layout(
   metadata_layout:Layout,
   item_layout:Layout,
   capacity:usize,
-) -> *mut () {
+) -> Result<(Layout, usize), TryNewError> {
   let midpoint = metadata_layout.size().next_multiple_of(item_layout.align());
-  let indexing_adjusted_capacity = indexing_adjusted_capacity(capacity);
-  let total_size = midpoint + item_layout.size() * indexing_adjusted_capacity;
-
+  let total_size = item_layout.size().checked_mul(indexing_adjusted_capacity(capacity))
+    .and_then(|slots_size| slots_size.checked_add(midpoint))
+    .ok_or(TryNewError::CapacityOverflow)?;
   let align = metadata_layout.align().max(item_layout.align());
-  let mem_ptr = unsafe { std::alloc::alloc(Layout::from_size_align_unchecked(total_size, align)) };
+  let layout = Layout::from_size_align(total_size, align).map_err(|_| TryNewError::CapacityOverflow)?;
+  return Ok((layout, midpoint))
+}
+
+/// `try_backing_store_layout` for capacities already known to fit.
+fn backing_store_layout(
+  metadata_layout:Layout,
+  item_layout:Layout,
+  capacity:usize,
+) -> (Layout, usize) {
+  let Ok(layout) = try_backing_store_layout(metadata_layout, item_layout, capacity) else { capacity_overflow(capacity) };
+  return layout
+}
+
+#[cold]
+pub(crate) fn capacity_overflow(capacity:usize) -> ! {
+  panic!("Queue layout can't be honored: a capacity of {} overflows the address space", capacity)
+}
 
-  let mid_ptr = mem_ptr.map_addr(|addr| addr + midpoint);
+#[inline(always)]
+fn alloc_ring_queue_backing_store(
+  metadata_layout:Layout,
+  item_layout:Layout,
+  capacity:usize,
+) -> Result<*mut (), TryNewError> {
+  let (layout, midpoint) = try_backing_store_layout(metadata_layout, item_layout, capacity)?;
+  let mem_ptr = alloc_ring_memory(layout, midpoint).ok_or(TryNewError::AllocFailed)?;
+  let mid_ptr = mem_ptr.as_ptr().map_addr(|addr| addr + midpoint);
+  return Ok(mid_ptr.cast::<()>())
+}
+
+/// Allocates memory for rings, failing when the `Alloc` failpoint is armed,
+/// and reports it to the alloc hook as the queue at `queue_offset` into it.
+/// `layout` must not be zero-sized.
+pub(crate) fn alloc_ring_memory(layout:Layout, queue_offset:usize) -> Option<core::ptr::NonNull<u8>> {
+  let mem_ptr = if fail_point(Failpoint::Alloc) { core::ptr::null_mut() } else { unsafe { std::alloc::alloc(layout) } };
+  let mem_ptr = core::ptr::NonNull::new(mem_ptr)?;
+  notify(AllocEvent::Alloc { queue: mem_ptr.addr().get() + queue_offset, size: layout.size() });
+  return Some(mem_ptr)
+}
 
-  return mid_ptr.cast::<()>()
+/// Frees memory from `alloc_ring_memory` and reports it to the alloc hook.
+///
+/// # Safety
+///
+/// `mem_ptr` must come from `alloc_ring_memory` with the same `layout`.
+pub(crate) unsafe fn dealloc_ring_memory(mem_ptr:*mut u8, layout:Layout, queue_offset:usize) {
+  unsafe { std::alloc::dealloc(mem_ptr, layout) };
+  notify(AllocEvent::Dealloc { queue: mem_ptr.addr() + queue_offset, size: layout.size() });
+}
+
+/// Number of items between the read and write indices of a ring with `capacity`.
+#[inline(always)]
+fn occupancy(read_index:u32, write_index:u32, capacity:usize) -> usize {
+  wrap_distance(read_index as usize + 1, write_index as usize, indexing_adjusted_capacity(capacity))
+}
+
+/// # Safety
+///
+/// `backing_store` must point past the metadata of a live ring.
+#[inline]
+pub(crate) unsafe fn occupancy_from_backing_store(backing_store: *mut (), capacity:usize) -> usize {
+  let mtd = unsafe { &*metadata_ptr(backing_store, Layout::new::<Metadata>()) };
+  occupancy(mtd.read_index.load(Ordering::Acquire), mtd.write_index.load(Ordering::Acquire), capacity)
+}
+
+// All pointers into the ring are computed by the functions below, from the
+// backing store pointer, so they keep the allocation's provenance.
+
+/// The metadata in front of the slots starting at `backing_store`.
+#[inline(always)]
+fn metadata_ptr(
+  backing_store: *mut (),
+  metadata_layout:Layout,
+) -> *mut Metadata {
+  let mtd_ptr = backing_store.map_addr(|addr| addr - metadata_layout.size()).cast::<Metadata>();
+  debug_assert!(mtd_ptr.is_aligned(), "Metadata at {:p} is misaligned", mtd_ptr);
+  return mtd_ptr
+}
+
+#[inline(always)]
+fn metadata_ref(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+) -> &Metadata {
+  unsafe { &*metadata_ptr(queue.backing_store, metadata_layout) }
+}
+
+#[inline(always)]
+fn metadata_mut(
+  queue: &mut RingQueueRaw,
+  metadata_layout:Layout,
+) -> &mut Metadata {
+  unsafe { &mut *metadata_ptr(queue.backing_store, metadata_layout) }
+}
+
+/// Slot `index` of the ring.
+#[inline(always)]
+fn slot_ptr(
+  queue: &RingQueueRaw,
+  item_layout:Layout,
+  index:usize,
+) -> *mut () {
+  debug_assert!(index < indexing_adjusted_capacity(queue.capacity), "Slot index {} is out of range", index);
+  queue.backing_store.map_addr(|addr| addr + index * item_layout.size())
 }
 
 #[inline(always)]
@@ -71,20 +735,45 @@ fn new_ring_queue(
   item_layout:Layout,
   capacity:usize,
 ) -> RingQueueRaw {
-  if capacity == 0 { panic!("Capacity must not be zero") }
-  let mid_ptr = alloc_ring_queue_backing_store(metadata_layout, item_layout, capacity);
-  let mtd_ptr = mid_ptr.map_addr(|addr| addr - metadata_layout.size());
-  let mtd_ptr = mtd_ptr.cast::<Metadata>();
-  let indexing_adjusted_capacity = indexing_adjusted_capacity(capacity);
-  let initial_read_index = indexing_adjusted_capacity - 1;
-  let initial_write_index = 0;
-  unsafe { mtd_ptr.write(Metadata {
-    read_index: AtomicU32::new(initial_read_index as _),
-    write_index: AtomicU32::new(initial_write_index)
-  }) };
+  check_capacity(capacity);
+  match try_new_ring_queue(metadata_layout, item_layout, capacity) {
+    Ok(result) => result,
+    Err(TryNewError::InvalidCapacity) => unreachable!(),
+    Err(TryNewError::CapacityOverflow) => capacity_overflow(capacity),
+    Err(TryNewError::AllocFailed) => std::alloc::handle_alloc_error(backing_store_layout(metadata_layout, item_layout, capacity).0),
+  }
+}
+
+fn try_new_ring_queue(
+  metadata_layout:Layout,
+  item_layout:Layout,
+  capacity:usize,
+) -> Result<RingQueueRaw, TryNewError> {
+  if capacity_problem(capacity).is_some() { return Err(TryNewError::InvalidCapacity) }
+  let mid_ptr = alloc_ring_queue_backing_store(metadata_layout, item_layout, capacity)?;
+  return Ok(init_ring_queue(mid_ptr, metadata_layout, capacity, true, None))
+}
+
+/// Writes fresh metadata in front of `mid_ptr` and wraps the ring around it.
+fn init_ring_queue(
+  mid_ptr: *mut (),
+  metadata_layout:Layout,
+  capacity:usize,
+  owns_memory: bool,
+  slot_copy: Option<SlotCopy>,
+) -> RingQueueRaw {
+  unsafe { metadata_ptr(mid_ptr, metadata_layout).write(Metadata::initial(capacity)) };
+  #[cfg(any(test, feature = "registry"))]
+  crate::registry::register(mid_ptr, capacity);
   let result = RingQueueRaw {
     backing_store: mid_ptr,
-    capacity: capacity
+    capacity,
+    owns_memory,
+    slot_copy,
+    #[cfg(feature = "mlock")]
+    locked: false,
+    #[cfg(any(debug_assertions, feature = "paranoid"))]
+    slot_states: Some((0 .. indexing_adjusted_capacity(capacity)).map(|_| AtomicU8::new(SlotState::Empty as u8)).collect()),
   };
   return result;
 }
@@ -94,69 +783,310 @@ fn destroy(
   metadata_layout:Layout,
   item_layout:Layout,
 ) {
+  #[cfg(any(test, feature = "registry"))]
+  crate::registry::unregister(queue.backing_store);
+  if !queue.owns_memory { return }
   let origin_ptr = mid_to_origin_ptr(queue.backing_store, metadata_layout, item_layout);
-  let midpoint = metadata_layout.size().next_multiple_of(item_layout.align());
-  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
-  let total_size = midpoint + item_layout.size() * indexing_adjusted_capacity;
-  let align = metadata_layout.align().max(item_layout.align());
-  unsafe {
-    let layout = Layout::from_size_align_unchecked(total_size, align);
-    std::alloc::dealloc(origin_ptr.cast::<u8>(), layout);
+  let (layout, _) = backing_store_layout(metadata_layout, item_layout, queue.capacity);
+  #[cfg(feature = "mlock")]
+  if queue.locked { unsafe { libc::munlock(origin_ptr.cast(), layout.size()) }; }
+  unsafe { dealloc_ring_memory(origin_ptr.cast::<u8>(), layout, queue.backing_store.addr() - origin_ptr.addr()) };
+}
+
+#[inline(always)]
+fn copy_into_slot(queue: &RingQueueRaw, src: *const u8, slot: *mut u8, size:usize) {
+  match queue.slot_copy {
+    Some(slot_copy) => unsafe { (slot_copy.into_slot)(src, slot, size) },
+    None => unsafe { copy_nonoverlapping(src, slot, size) },
+  }
+}
+
+#[inline(always)]
+fn copy_from_slot(queue: &RingQueueRaw, slot: *const u8, dst: *mut u8, size:usize) {
+  match queue.slot_copy {
+    Some(slot_copy) => unsafe { (slot_copy.from_slot)(slot, dst, size) },
+    None => unsafe { copy_nonoverlapping(slot, dst, size) },
   }
 }
 
 
-fn enqueue_item_prim(
+#[inline]
+pub(crate) fn enqueue_item_prim(
   queue: &RingQueueRaw,
   metadata_layout:Layout,
   item_layout:Layout,
   item_data_src_ptr: *const (),
 ) -> bool {
-  let backing_store_ptr = queue.backing_store;
-  let mtd_ptr = backing_store_ptr.map_addr(|addr| addr - metadata_layout.size());
-  let mtd_ptr = unsafe{&mut *mtd_ptr.cast::<Metadata>()};
-  let prior_write_index = mtd_ptr.write_index.load(Ordering::Acquire);
-  let bumped_index = prior_write_index + 1;
-  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
-  let next_write_index = (bumped_index) * (!(bumped_index == (indexing_adjusted_capacity as u32)) as u32);
-  let current_read_index = mtd_ptr.read_index.load(Ordering::Relaxed);
+  let mtd = metadata_ref(queue, metadata_layout);
+  hook(HookPoint::ProducerLoadWriteIndex);
+  let prior_write_index = mtd.write_index.load(Ordering::Relaxed);
+  let next_write_index = bump_index(prior_write_index, queue.capacity);
+  hook(HookPoint::ProducerLoadReadIndex);
+  let current_read_index = mtd.read_index.load(Ordering::Acquire);
   let full = next_write_index == current_read_index;
   if full {
     return false
   }
-  let write_slot = backing_store_ptr.map_addr(|addr| addr + ((prior_write_index as usize) * item_layout.size()));
-  unsafe { copy_nonoverlapping(item_data_src_ptr.cast::<u8>(), write_slot.cast::<u8>(), item_layout.size()) };
-  mtd_ptr.write_index.store(next_write_index, Ordering::Release);
+  let write_slot = slot_ptr(queue, item_layout, prior_write_index as usize);
+  tag_slot_written(queue, prior_write_index);
+  copy_into_slot(queue, item_data_src_ptr.cast::<u8>(), write_slot.cast::<u8>(), item_layout.size());
+  hook(HookPoint::ProducerStoreWriteIndex);
+  mtd.write_index.store(next_write_index, Ordering::Release);
+  record(queue.backing_store, TraceOp::Push, mtd);
 
   return true
 }
 
 
-fn dequeue_item_prim(
+#[inline]
+pub(crate) fn dequeue_item_prim(
   queue: &RingQueueRaw,
   metadata_layout:Layout,
   item_layout:Layout,
   item_data_dst_ptr: *mut (),
 ) -> bool {
-  let backing_store_ptr = queue.backing_store;
-  let mtd_ptr = backing_store_ptr.map_addr(|addr| addr - metadata_layout.size());
-  let mtd_ptr = unsafe{&mut *mtd_ptr.cast::<Metadata>()};
-  let read_index = mtd_ptr.read_index.load(Ordering::Acquire);
-  let bumped_index = read_index + 1;
-  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
-  let next_index = bumped_index * (!(bumped_index == (indexing_adjusted_capacity as u32)) as u32);
-  let write_index = mtd_ptr.write_index.load(Ordering::Relaxed);
-  let empty = next_index == write_index;
+  let read_slot = peek_item_prim(queue, metadata_layout, item_layout);
+  if read_slot.is_null() {
+    return false;
+  }
+  copy_from_slot(queue, read_slot.cast::<u8>(), item_data_dst_ptr.cast::<u8>(), item_layout.size());
+  release_item_prim(queue, metadata_layout);
+
+  return true;
+}
+
+fn enqueue_item_unsync_prim(
+  queue: &mut RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+  item_data_src_ptr: *const (),
+) -> bool {
+  let (backing_store, capacity) = (queue.backing_store, queue.capacity);
+  let mtd = metadata_mut(queue, metadata_layout);
+  let prior_write_index = *mtd.write_index.get_mut();
+  let next_write_index = bump_index(prior_write_index, capacity);
+  let full = next_write_index == *mtd.read_index.get_mut();
+  if full {
+    return false
+  }
+  let write_slot = slot_ptr(queue, item_layout, prior_write_index as usize);
+  tag_slot_written(queue, prior_write_index);
+  copy_into_slot(queue, item_data_src_ptr.cast::<u8>(), write_slot.cast::<u8>(), item_layout.size());
+  let mtd = metadata_mut(queue, metadata_layout);
+  *mtd.write_index.get_mut() = next_write_index;
+  record(backing_store, TraceOp::Push, mtd);
+
+  return true
+}
+
+fn dequeue_item_unsync_prim(
+  queue: &mut RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+  item_data_dst_ptr: *mut (),
+) -> bool {
+  let (backing_store, capacity) = (queue.backing_store, queue.capacity);
+  let mtd = metadata_mut(queue, metadata_layout);
+  let next_index = bump_index(*mtd.read_index.get_mut(), capacity);
+  let empty = next_index == *mtd.write_index.get_mut();
   if empty {
     return false;
   }
-  let read_slot = backing_store_ptr.map_addr(|addr| addr + (next_index as usize) * item_layout.size());
-  unsafe { copy_nonoverlapping(read_slot.cast::<u8>(), item_data_dst_ptr.cast::<u8>(), item_layout.size()) };
-  mtd_ptr.read_index.store(next_index, Ordering::Release);
+  let read_slot = slot_ptr(queue, item_layout, next_index as usize);
+  tag_slot_consumed(queue, next_index);
+  copy_from_slot(queue, read_slot.cast::<u8>(), item_data_dst_ptr.cast::<u8>(), item_layout.size());
+  let mtd = metadata_mut(queue, metadata_layout);
+  *mtd.read_index.get_mut() = next_index;
+  record(backing_store, TraceOp::Pop, mtd);
 
   return true;
 }
 
+/// Slots from index `from` up to index `to` going around the ring, for
+/// `from <= indexing_adjusted_capacity` and `to < indexing_adjusted_capacity`.
+/// Spelled without `%` so the hot paths carry no division-by-zero check.
+#[inline(always)]
+fn wrap_distance(from:usize, to:usize, indexing_adjusted_capacity:usize) -> usize {
+  let distance = to + indexing_adjusted_capacity - from;
+  if distance >= indexing_adjusted_capacity { distance - indexing_adjusted_capacity } else { distance }
+}
+
+/// Contiguous run of free slots starting at the write index, as (first slot, length).
+#[inline]
+fn writable_chunk_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+) -> (*mut (), usize) {
+  let mtd = metadata_ref(queue, metadata_layout);
+  hook(HookPoint::ProducerLoadWriteIndex);
+  let write_index = mtd.write_index.load(Ordering::Relaxed) as usize;
+  hook(HookPoint::ProducerLoadReadIndex);
+  let read_index = mtd.read_index.load(Ordering::Acquire) as usize;
+  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
+  let free = wrap_distance(write_index + 1, read_index, indexing_adjusted_capacity);
+  let contiguous = free.min(indexing_adjusted_capacity - write_index);
+  let first_slot = slot_ptr(queue, item_layout, write_index);
+  return (first_slot, contiguous)
+}
+
+/// Publishes `count` slots filled after `writable_chunk_prim`.
+#[inline]
+fn commit_write_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  count:usize,
+) {
+  let mtd = metadata_ref(queue, metadata_layout);
+  let mut write_index = mtd.write_index.load(Ordering::Relaxed);
+  for _ in 0 .. count {
+    tag_slot_written(queue, write_index);
+    write_index = bump_index(write_index, queue.capacity);
+  }
+  hook(HookPoint::ProducerStoreWriteIndex);
+  mtd.write_index.store(write_index, Ordering::Release);
+  record(queue.backing_store, TraceOp::CommitWrite(count), mtd);
+}
+
+/// Contiguous run of readable slots after the read index, as (first slot, length).
+#[inline]
+fn readable_chunk_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+) -> (*mut (), usize) {
+  let mtd = metadata_ref(queue, metadata_layout);
+  hook(HookPoint::ConsumerLoadReadIndex);
+  let read_index = mtd.read_index.load(Ordering::Relaxed) as usize;
+  hook(HookPoint::ConsumerLoadWriteIndex);
+  let write_index = mtd.write_index.load(Ordering::Acquire) as usize;
+  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
+  let first_index = bump_index(read_index as u32, queue.capacity) as usize;
+  let used = wrap_distance(read_index + 1, write_index, indexing_adjusted_capacity);
+  let contiguous = used.min(indexing_adjusted_capacity - first_index);
+  let first_slot = slot_ptr(queue, item_layout, first_index);
+  return (first_slot, contiguous)
+}
+
+/// Hands `count` slots read after `readable_chunk_prim` back to the producer.
+#[inline]
+fn release_read_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  count:usize,
+) {
+  let mtd = metadata_ref(queue, metadata_layout);
+  let mut read_index = mtd.read_index.load(Ordering::Relaxed);
+  for _ in 0 .. count {
+    read_index = bump_index(read_index, queue.capacity);
+    tag_slot_consumed(queue, read_index);
+  }
+  hook(HookPoint::ConsumerStoreReadIndex);
+  mtd.read_index.store(read_index, Ordering::Release);
+  record(queue.backing_store, TraceOp::ReleaseRead(count), mtd);
+}
+
+/// Returns a pointer to the next readable slot, or null if the queue is empty.
+/// The slot stays owned by the consumer until `release_item_prim`.
+#[inline]
+fn peek_item_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+) -> *mut () {
+  let mtd = metadata_ref(queue, metadata_layout);
+  hook(HookPoint::ConsumerLoadReadIndex);
+  let read_index = mtd.read_index.load(Ordering::Relaxed);
+  let next_index = bump_index(read_index, queue.capacity);
+  hook(HookPoint::ConsumerLoadWriteIndex);
+  let write_index = mtd.write_index.load(Ordering::Acquire);
+  let empty = next_index == write_index;
+  if empty {
+    return core::ptr::null_mut();
+  }
+  let read_slot = slot_ptr(queue, item_layout, next_index as usize);
+
+  return read_slot;
+}
+
+/// Returns a pointer to the readable slot `n` items past the next one, or
+/// null if fewer than `n + 1` items are queued.
+#[inline]
+fn peek_nth_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+  item_layout:Layout,
+  n:usize,
+) -> *mut () {
+  let mtd = metadata_ref(queue, metadata_layout);
+  hook(HookPoint::ConsumerLoadReadIndex);
+  let read_index = mtd.read_index.load(Ordering::Relaxed) as usize;
+  hook(HookPoint::ConsumerLoadWriteIndex);
+  let write_index = mtd.write_index.load(Ordering::Acquire) as usize;
+  let indexing_adjusted_capacity = indexing_adjusted_capacity(queue.capacity);
+  if n >= wrap_distance(read_index + 1, write_index, indexing_adjusted_capacity) {
+    return core::ptr::null_mut();
+  }
+  let index = read_index + 1 + n;
+  let index = if index >= indexing_adjusted_capacity { index - indexing_adjusted_capacity } else { index };
+  return slot_ptr(queue, item_layout, index)
+}
+
+/// Hands the slot returned by the last successful `peek_item_prim` back to the producer.
+#[inline]
+fn release_item_prim(
+  queue: &RingQueueRaw,
+  metadata_layout:Layout,
+) {
+  let mtd = metadata_ref(queue, metadata_layout);
+  let read_index = mtd.read_index.load(Ordering::Relaxed);
+  let next_index = bump_index(read_index, queue.capacity);
+  tag_slot_consumed(queue, next_index);
+  hook(HookPoint::ConsumerStoreReadIndex);
+  mtd.read_index.store(next_index, Ordering::Release);
+  record(queue.backing_store, TraceOp::Pop, mtd);
+}
+
+/// Model checking harnesses, run with `cargo kani`.
+#[cfg(kani)]
+mod proofs {
+  use super::*;
+
+  /// Any sequence of pushes and pops on a small ring behaves like a bounded
+  /// FIFO: a push succeeds exactly when fewer than `capacity` items are
+  /// queued and never overwrites an unread one, and a pop succeeds exactly
+  /// when an item is queued and returns the oldest.
+  #[kani::proof]
+  #[kani::unwind(10)]
+  fn push_pop_sequences_match_a_bounded_fifo() {
+    let mtd_l = Layout::new::<Metadata>();
+    let item_l = Layout::new::<u8>();
+    let capacity: usize = kani::any();
+    kani::assume(capacity >= 1 && capacity <= 3);
+    let q = new_ring_queue(mtd_l, item_l, capacity);
+    // the model: items are numbered in push order, `oldest .. oldest + queued` are in the ring
+    let (mut oldest, mut queued) = (0u8, 0usize);
+    for _ in 0 .. 8 {
+      if kani::any() {
+        let item = oldest + queued as u8;
+        let ok = enqueue_item_prim(&q, mtd_l, item_l, &raw const item as _);
+        assert!(ok == (queued < capacity));
+        if ok { queued += 1 }
+      } else {
+        let mut item = u8::MAX;
+        let ok = dequeue_item_prim(&q, mtd_l, item_l, &raw mut item as _);
+        assert!(ok == (queued > 0));
+        if ok {
+          assert!(item == oldest);
+          oldest += 1;
+          queued -= 1;
+        }
+      }
+    }
+    destroy(q, mtd_l, item_l);
+  }
+}
+
 #[test]
 fn basic() {
   let mtd_l = Layout::new::<Metadata>();
@@ -209,52 +1139,362 @@ fn basic3() {
 #[test]
 fn mt_test() {
   const CAPACITY : usize = 4096 * 16;
-  let q = RingQueue::<u32>::new(CAPACITY);
-  let sync_var = AtomicU32::new(0);
-  let producer = unsafe {
-    std::thread::Builder::new().spawn_unchecked({
-      let sync_var = &sync_var;
-      let q = &q;
-      move || {
-        let _ = sync_var.fetch_add(1, Ordering::AcqRel);
-        while sync_var.load(Ordering::Relaxed) != 2 {}
-        fence(Ordering::SeqCst);
-        for i in 0 .. CAPACITY {
-          let i = MaybeUninit::new(i as u32);
-          let ok = q.enqueue_item(&i);
-          assert!(ok);
-          // todo: random sleep here?
-        }
-      }
-    })
-  };
-  let consumer = unsafe {
-    std::thread::Builder::new().spawn_unchecked({
-      let sync_var = &sync_var;
-      let q = &q;
-      move || {
-        let _ = sync_var.fetch_add(1, Ordering::AcqRel);
-        while sync_var.load(Ordering::Relaxed) != 2 {}
-        let mut result = Vec::new();
-        result.reserve(CAPACITY);
-        fence(Ordering::SeqCst);
-        let mut recv_count = 0;
-        let mut i = MaybeUninit::uninit();
-        loop {
-          let ok = q.dequeue_item(&mut i);
-          if ok {
-            result.push(i.assume_init());
-            recv_count += 1;
-            if recv_count == CAPACITY { break }
-          }
-        }
-        result
-      }
-    })
-  };
-  let val = consumer.unwrap().join().unwrap();
-  producer.unwrap().join().unwrap();
+  let ((), val) = crate::pipeline::<u32, _, _>(CAPACITY, |mut producer| {
+    for i in 0 .. CAPACITY {
+      let ok = producer.push(i as u32).is_ok();
+      assert!(ok);
+    }
+  }, |mut consumer| {
+    let mut result = Vec::with_capacity(CAPACITY);
+    while result.len() != CAPACITY {
+      if let Some(i) = consumer.pop() { result.push(i) }
+    }
+    result
+  });
   for (a,b) in val.iter().zip(0..) {
     assert!(*a == b)
   }
-}
\ No newline at end of file
+}
+#[test]
+fn deadline_expires_on_full_queue() {
+  let q = RingQueue::<u32>::new(2);
+  let clock = crate::clock::MockClock::new();
+  for i in 0 .. 2 {
+    assert!(q.enqueue_item_deadline(&MaybeUninit::new(i), &clock, 10));
+  }
+  let ok = q.enqueue_item_deadline(&MaybeUninit::new(2), &clock, 10);
+  assert!(!ok);
+  assert!(clock.ticks.get() == 11);
+  let mut out = MaybeUninit::uninit();
+  assert!(q.dequeue_item(&mut out));
+  assert!(q.enqueue_item_deadline(&MaybeUninit::new(2), &clock, 10));
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn forced_interleaving_push_during_pop() {
+  use std::sync::mpsc::channel;
+  let q = RingQueue::<u32>::new(4);
+  let (at_load_tx, at_load_rx) = channel::<()>();
+  let (resume_tx, resume_rx) = channel::<()>();
+  std::thread::scope(|s| {
+    let q = &q;
+    let consumer = s.spawn(move || {
+      crate::hooks::set_thread_hook(move |point| {
+        if point == HookPoint::ConsumerLoadWriteIndex {
+          at_load_tx.send(()).unwrap();
+          resume_rx.recv().unwrap();
+        }
+      });
+      let mut out = MaybeUninit::uninit();
+      let first = q.dequeue_item(&mut out);
+      crate::hooks::clear_thread_hook();
+      (first, q.dequeue_item(&mut out), unsafe { out.assume_init() })
+    });
+    // the consumer is parked right before it looks at the write index
+    at_load_rx.recv().unwrap();
+    assert!(q.enqueue_item(&MaybeUninit::new(42)));
+    resume_tx.send(()).unwrap();
+    let (first, second, value) = consumer.join().unwrap();
+    // the write index is loaded after the push, so the item is already visible
+    assert!(first && !second && value == 42);
+  });
+  unsafe { q.dispose() };
+}
+
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+#[test]
+#[should_panic(expected = "is read while Consumed")]
+fn slot_tagging_catches_double_pop() {
+  let mtd_l = Layout::new::<Metadata>();
+  let item_l = Layout::new::<u32>();
+  let q = new_ring_queue(mtd_l, item_l, 4);
+  for item in 0u32 .. 2 {
+    assert!(enqueue_item_prim(&q, mtd_l, item_l, &raw const item as _));
+  }
+  let mut out = MaybeUninit::<u32>::uninit();
+  assert!(dequeue_item_prim(&q, mtd_l, item_l, out.as_mut_ptr() as _));
+  // corrupt the read index so the consumed slot is handed out again
+  let mtd_ptr = metadata_ptr(q.backing_store, mtd_l);
+  unsafe { (*mtd_ptr).read_index.store(indexing_adjusted_capacity(4) as u32 - 1, Ordering::Relaxed) };
+  dequeue_item_prim(&q, mtd_l, item_l, out.as_mut_ptr() as _);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic = "out of range"]
+fn out_of_range_index_is_caught_before_the_slot_access() {
+  let mtd_l = Layout::new::<Metadata>();
+  let item_l = Layout::new::<u32>();
+  let q = new_ring_queue(mtd_l, item_l, 4);
+  let mtd_ptr = metadata_ptr(q.backing_store, mtd_l);
+  unsafe { (*mtd_ptr).write_index.store(indexing_adjusted_capacity(4) as u32, Ordering::Relaxed) };
+  let item = 0u32;
+  enqueue_item_prim(&q, mtd_l, item_l, &raw const item as _);
+}
+
+#[test]
+fn frozen_queue_reports_paused() {
+  let q = RingQueue::<u32>::new(4);
+  assert!(q.try_enqueue_item(&MaybeUninit::new(1)).is_ok());
+  q.freeze();
+  assert!(q.try_enqueue_item(&MaybeUninit::new(2)) == Err(EnqueueError::Paused));
+  let mut out = MaybeUninit::uninit();
+  assert!(q.dequeue_item(&mut out));
+  assert!(unsafe { out.assume_init() } == 1);
+  q.resume();
+  assert!(q.try_enqueue_item(&MaybeUninit::new(3)).is_ok());
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn unsync_prefill_then_split() {
+  let mut q = RingQueue::<u32>::new(8);
+  let mut unsync = q.get_mut();
+  for i in 0 .. 8 { assert!(unsync.push(i).is_ok()) }
+  assert!(unsync.push(8) == Err(8));
+  assert!(unsync.pop() == Some(0));
+  assert!(unsync.push(8).is_ok());
+  let (_producer, mut consumer) = q.split();
+  for i in 1 .. 9 { assert!(consumer.pop() == Some(i)) }
+  assert!(consumer.pop().is_none());
+}
+
+#[test]
+fn reset_drops_items_and_restores_indices() {
+  let token = std::sync::Arc::new(());
+  let mut q = RingQueue::new(3);
+  for _ in 0 .. 2 { assert!(q.get_mut().push(token.clone()).is_ok()) }
+  q.freeze();
+  q.reset();
+  assert!(std::sync::Arc::strong_count(&token) == 1);
+  assert!(!q.is_frozen());
+  let mtd = metadata_ref(&q.raw_queue, Layout::new::<Metadata>());
+  assert!(mtd.read_index.load(Ordering::Relaxed) == 4 && mtd.write_index.load(Ordering::Relaxed) == 0);
+  for _ in 0 .. 3 { assert!(q.get_mut().push(token.clone()).is_ok()) }
+  q.reset();
+  unsafe { q.dispose() };
+}
+
+#[cfg(any(debug_assertions, feature = "paranoid"))]
+#[test]
+fn concurrent_producers_are_detected() {
+  use std::sync::mpsc::channel;
+  let q = RingQueue::<u32>::new(4);
+  let (parked_tx, parked_rx) = channel::<()>();
+  let (resume_tx, resume_rx) = channel::<()>();
+  std::thread::scope(|s| {
+    let q = &q;
+    let first = s.spawn(move || {
+      crate::hooks::set_thread_hook(move |point| {
+        if point == HookPoint::ProducerStoreWriteIndex {
+          parked_tx.send(()).unwrap();
+          resume_rx.recv().unwrap();
+        }
+      });
+      q.enqueue_item(&MaybeUninit::new(1))
+    });
+    parked_rx.recv().unwrap();
+    let second = s.spawn(move || q.enqueue_item(&MaybeUninit::new(2))).join();
+    resume_tx.send(()).unwrap();
+    assert!(first.join().unwrap());
+    let message = second.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("producer"));
+  });
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn packed_metadata_is_reported() {
+  #[repr(align(64))]
+  struct Line(#[allow(dead_code)] [u8; 64]);
+  let q = RingQueue::<Line>::new(4);
+  // line-sized slots start on their own line, but the indices stay packed together
+  let report = q.cache_line_report();
+  assert!(report.indices_share_line && !report.metadata_shares_first_slot && !report.first_slot_shares_last_slot);
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn warming_keeps_queued_items() {
+  let mut q = RingQueue::<[u8; 100]>::new_warm(5);
+  for i in 0 .. 3 { assert!(q.enqueue_item(&MaybeUninit::new([i; 100]))) }
+  q.warm();
+  let mut out = MaybeUninit::uninit();
+  for i in 0 .. 3 { assert!(q.dequeue_item(&mut out) && unsafe { out.assume_init() } == [i; 100]) }
+  unsafe { q.dispose() };
+}
+
+#[cfg(feature = "mlock")]
+#[test]
+fn locked_queue_still_moves_items() {
+  let mut q = RingQueue::<u64>::new(64);
+  // well inside the default RLIMIT_MEMLOCK
+  q.lock_memory().unwrap();
+  q.lock_memory().unwrap();
+  assert!(q.enqueue_item(&MaybeUninit::new(7)));
+  let mut out = MaybeUninit::uninit();
+  assert!(q.dequeue_item(&mut out) && unsafe { out.assume_init() } == 7);
+  unsafe { q.dispose() };
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn rep_movsb_slot_copy() {
+  let q = unsafe { RingQueue::<[u8; 100]>::with_slot_copy(3, SlotCopy::REP_MOVSB) };
+  for i in 0 .. 3 { assert!(q.enqueue_item(&MaybeUninit::new([i; 100]))) }
+  let mut item = MaybeUninit::uninit();
+  for i in 0 .. 3 {
+    assert!(q.dequeue_item(&mut item));
+    assert!(unsafe { item.assume_init() } == [i; 100]);
+  }
+  unsafe { q.dispose() };
+}
+
+#[test]
+#[should_panic = "Capacity must fit the 32-bit indices"]
+fn capacity_beyond_index_range_is_rejected() {
+  let _ = RingQueue::<()>::new(u32::MAX as usize - 1);
+}
+
+#[test]
+fn into_vec_keeps_order_across_the_wrap() {
+  let mut q = RingQueue::<String>::new(3);
+  let mut unsync = q.get_mut();
+  for word in ["a", "b", "c"] { unsync.push(word.into()).unwrap() }
+  assert!(unsync.pop().as_deref() == Some("a"));
+  unsync.push("d".into()).unwrap();
+  assert!(q.into_vec() == ["b", "c", "d"]);
+}
+
+#[test]
+fn prefilled_queue_starts_with_the_items() {
+  let q = RingQueue::from_iter_with_capacity(0u32 .. 3, 5);
+  let mut item = MaybeUninit::new(3);
+  assert!(q.enqueue_item(&item) && q.enqueue_item(&item));
+  assert!(!q.enqueue_item(&item));
+  assert!(q.dequeue_item(&mut item) && unsafe { item.assume_init() } == 0);
+  assert!(RingQueue::from(vec![1, 2]).into_vec() == [1, 2]);
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn shrink_keeps_queued_items_in_order() {
+  let mut q = RingQueue::from_iter_with_capacity(0u32 .. 6, 8);
+  let mut item = MaybeUninit::uninit();
+  for _ in 0 .. 4 { assert!(q.dequeue_item(&mut item)) }
+  q.shrink(2);
+  assert!(!q.enqueue_item(&MaybeUninit::new(6)));
+  assert!(q.into_vec() == [4, 5]);
+}
+
+#[test]
+fn queue_in_caller_memory() {
+  let layout = RingQueue::<[u32; 3]>::memory_layout(4);
+  let memory = unsafe { std::alloc::alloc(layout) };
+  let q = unsafe { RingQueue::<[u32; 3]>::from_memory(core::ptr::NonNull::new(memory).unwrap(), 4, SlotCopy::VOLATILE) };
+  for round in 0 .. 3 {
+    for i in 0 .. 4 { assert!(q.enqueue_item(&MaybeUninit::new([round, i, 7]))) }
+    let mut out = MaybeUninit::uninit();
+    for i in 0 .. 4 {
+      assert!(q.dequeue_item(&mut out));
+      assert!(unsafe { out.assume_init() } == [round, i, 7]);
+    }
+  }
+  unsafe { q.dispose() };
+  unsafe { std::alloc::dealloc(memory, layout) };
+}
+
+#[test]
+fn over_aligned_items_land_in_aligned_slots() {
+  fn check<T: Copy + PartialEq + core::fmt::Debug>(make: impl Fn(u8) -> T) {
+    let q = RingQueue::<T>::new(3);
+    let mut out = MaybeUninit::uninit();
+    // a few laps, so every slot gets used
+    for i in 0 .. 12 {
+      assert!(q.enqueue_item(&MaybeUninit::new(make(i))));
+      assert!(q.peek_item().unwrap().is_aligned());
+      assert!(q.dequeue_item(&mut out) && unsafe { out.assume_init() } == make(i));
+    }
+    unsafe { q.dispose() };
+    let layout = RingQueue::<T>::memory_layout(3);
+    assert!(layout.align() == align_of::<T>());
+    let memory = core::ptr::NonNull::new(unsafe { std::alloc::alloc(layout) }).unwrap();
+    let q = unsafe { RingQueue::<T>::in_memory(memory, 3, None) };
+    assert!(q.enqueue_item(&MaybeUninit::new(make(1))));
+    assert!(q.peek_item().unwrap().is_aligned());
+    unsafe { q.dispose() };
+    unsafe { std::alloc::dealloc(memory.as_ptr(), layout) };
+  }
+  #[derive(Clone, Copy, PartialEq, Debug)]
+  #[repr(align(32))]
+  struct Align32([u8; 3]);
+  #[derive(Clone, Copy, PartialEq, Debug)]
+  #[repr(align(64))]
+  struct Align64(u8);
+  #[derive(Clone, Copy, PartialEq, Debug)]
+  #[repr(align(128))]
+  struct Align128([u8; 130]);
+  check(|i| Align32([i; 3]));
+  check(Align64);
+  check(|i| Align128([i; 130]));
+}
+
+#[test]
+#[should_panic = "Queue layout can't be honored"]
+fn oversized_layout_is_rejected() {
+  let _ = RingQueue::<[u64; 1 << 40]>::new(1 << 20);
+}
+
+#[test]
+fn overflowing_capacity_is_an_error() {
+  // past `isize::MAX` once padded, and past `usize::MAX` outright
+  assert!(RingQueue::<[u64; 1 << 40]>::try_new(1 << 20).err() == Some(TryNewError::CapacityOverflow));
+  assert!(RingQueue::<[u8; 1 << 50]>::try_new(1 << 20).err() == Some(TryNewError::CapacityOverflow));
+  let q = RingQueue::<u64>::try_new(4).unwrap();
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn capacity_out_of_bounds_is_an_error() {
+  assert!(RingQueue::<u64>::try_new(0).err() == Some(TryNewError::InvalidCapacity));
+  assert!(RingQueue::<u64>::try_new(u32::MAX as usize - 1).err() == Some(TryNewError::InvalidCapacity));
+  assert!(RingQueue::<u64>::try_new(usize::MAX).err() == Some(TryNewError::InvalidCapacity));
+  let q = RingQueue::<u64>::try_new(1).unwrap();
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn try_new_never_panics_on_capacities_too_large_to_lay_out() {
+  let outcome = std::panic::catch_unwind(|| [
+    RingQueue::<u64>::try_new(usize::MAX).err(),
+    RingQueue::<[u8; 1 << 40]>::try_new(u32::MAX as usize - 2).err(),
+    RingQueue::<[u64; 1 << 40]>::try_new(1 << 20).err(),
+  ]);
+  assert!(outcome.ok() == Some([Some(TryNewError::InvalidCapacity), Some(TryNewError::CapacityOverflow), Some(TryNewError::CapacityOverflow)]));
+}
+
+#[test]
+fn failed_allocation_is_an_error() {
+  use crate::failpoints::{arm, disarm};
+  arm(Failpoint::Alloc, 1);
+  assert!(RingQueue::<u64>::try_new(4).err() == Some(TryNewError::AllocFailed));
+  disarm(Failpoint::Alloc);
+  let q = RingQueue::<u64>::try_new(4).unwrap();
+  assert!(q.enqueue_item(&MaybeUninit::new(1)));
+  unsafe { q.dispose() };
+}
+
+#[test]
+fn raw_parts_round_trip_keeps_items() {
+  #[repr(C)]
+  struct Host {
+    queue: *mut u8,
+    capacity: usize,
+  }
+  let q = RingQueue::from_iter_with_capacity(["a".to_string(), "b".to_string()], 4);
+  let (queue, capacity) = q.into_raw_parts();
+  let host = Host { queue: queue.as_ptr(), capacity };
+  let q = unsafe { RingQueue::<String>::from_raw_parts(core::ptr::NonNull::new(host.queue).unwrap(), host.capacity) };
+  assert!(q.into_vec() == ["a", "b"]);
+}
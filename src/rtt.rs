@@ -0,0 +1,47 @@
+use crate::RingQueue;
+use std::time::{Duration, Instant};
+
+/// Round-trip latencies measured by `RingQueue::measure_rtt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttReport {
+  pub p50: Duration,
+  pub p99: Duration,
+  pub max: Duration,
+}
+
+impl RingQueue<Instant> {
+  /// Bounces a timestamp `iterations` times between two temporary threads,
+  /// over one ring each way with both sides spinning, and reports the
+  /// round-trip percentiles. Meant to be run at startup to check where the
+  /// threads land and what a handoff costs there.
+  pub fn measure_rtt(iterations: usize) -> RttReport {
+    assert!(iterations != 0, "Iterations must not be zero");
+    let (mut ping, mut ping_echo) = RingQueue::<Instant>::new(1).split();
+    let (mut pong_echo, mut pong) = RingQueue::<Instant>::new(1).split();
+    let mut samples = std::thread::scope(|s| {
+      s.spawn(move || {
+        for _ in 0 .. iterations {
+          let sent = loop { if let Some(sent) = ping_echo.pop() { break sent } };
+          if pong_echo.push(sent).is_err() { unreachable!() }
+        }
+      });
+      s.spawn(move || {
+        (0 .. iterations).map(|_| {
+          if ping.push(Instant::now()).is_err() { unreachable!() }
+          let sent = loop { if let Some(sent) = pong.pop() { break sent } };
+          sent.elapsed()
+        }).collect::<Vec<_>>()
+      }).join().unwrap()
+    });
+    samples.sort_unstable();
+    let percentile = |percent: usize| samples[(samples.len() - 1) * percent / 100];
+    RttReport { p50: percentile(50), p99: percentile(99), max: percentile(100) }
+  }
+}
+
+#[test]
+fn rtt_percentiles_are_ordered() {
+  let report = RingQueue::measure_rtt(20);
+  assert!(report.p50 <= report.p99 && report.p99 <= report.max);
+  assert!(report.max > Duration::ZERO);
+}
@@ -0,0 +1,97 @@
+use crate::Consumer;
+
+/// Fan-in over several consumers, serving them in turn so that a busy ring
+/// early in the list can't starve the ones after it.
+///
+/// Each ring gets up to its weight in consecutive pops before the turn moves
+/// on, and an empty ring gives up the rest of its turn. With `new` all
+/// weights are 1, which is plain round-robin.
+pub struct Select<T> {
+  consumers: Vec<Consumer<T>>,
+  weights: Vec<u32>,
+  /// the ring whose turn it is
+  turn: usize,
+  /// pops left in the current turn
+  credits: u32,
+  /// pops served to other rings while each ring had items waiting
+  starved: Vec<u64>,
+}
+
+impl <T> Select<T> {
+  pub fn new(consumers: Vec<Consumer<T>>) -> Self {
+    let weights = vec![1; consumers.len()];
+    Self::with_weights(consumers, weights)
+  }
+  /// Ring `i` is served up to `weights[i]` items per turn.
+  pub fn with_weights(consumers: Vec<Consumer<T>>, weights: Vec<u32>) -> Self {
+    assert!(consumers.len() == weights.len(), "Expected one weight per consumer");
+    assert!(weights.iter().all(|weight| *weight != 0), "Weights must not be zero");
+    let credits = weights.first().copied().unwrap_or(0);
+    let starved = vec![0; consumers.len()];
+    Self { consumers, weights, turn: 0, credits, starved }
+  }
+  fn next_turn(&mut self) {
+    self.turn = (self.turn + 1) % self.consumers.len();
+    self.credits = self.weights[self.turn];
+  }
+  /// Pops the next item by the fairness policy, with the index of the ring it came from.
+  /// None if every ring is empty.
+  pub fn pop(&mut self) -> Option<(usize, T)> {
+    for _ in 0 .. self.consumers.len() {
+      let index = self.turn;
+      let Some(item) = self.consumers[index].pop() else {
+        self.next_turn();
+        continue
+      };
+      self.credits -= 1;
+      if self.credits == 0 { self.next_turn() }
+      for (other, consumer) in self.consumers.iter().enumerate() {
+        if other != index && consumer.occupancy() != 0 { self.starved[other] += 1 }
+      }
+      return Some((index, item))
+    }
+    return None
+  }
+  /// How many pops went to other rings while ring `index` had items queued.
+  pub fn starvation(&self, index: usize) -> u64 {
+    self.starved[index]
+  }
+  pub fn consumers(&self) -> &[Consumer<T>] {
+    &self.consumers
+  }
+  pub fn into_inner(self) -> Vec<Consumer<T>> {
+    self.consumers
+  }
+}
+
+#[test]
+fn round_robin_serves_every_busy_ring() {
+  let (mut producers, consumers): (Vec<_>, Vec<_>) = (0 .. 16).map(|_| crate::RingQueue::<u32>::new(4).split()).unzip();
+  for producer in &mut producers {
+    for item in 0 .. 4 { assert!(producer.push(item).is_ok()) }
+  }
+  let mut select = Select::new(consumers);
+  let first_round = (0 .. 16).map(|_| select.pop().unwrap()).collect::<Vec<_>>();
+  assert!(first_round == (0 .. 16).map(|index| (index, 0)).collect::<Vec<_>>());
+  while select.pop().is_some() {}
+  // every ring waited for the others the same number of times, give or take a round
+  let waits = (0 .. 16).map(|index| select.starvation(index)).collect::<Vec<_>>();
+  assert!(waits.iter().max().unwrap() - waits.iter().min().unwrap() <= 15);
+}
+
+#[test]
+fn weights_split_pops_between_rings() {
+  let (mut heavy, heavy_consumer) = crate::RingQueue::<u32>::new(8).split();
+  let (mut light, light_consumer) = crate::RingQueue::<u32>::new(8).split();
+  for item in 0 .. 8 {
+    assert!(heavy.push(item).is_ok());
+    assert!(light.push(item).is_ok());
+  }
+  let mut select = Select::with_weights(vec![heavy_consumer, light_consumer], vec![3, 1]);
+  let sources = (0 .. 8).map(|_| select.pop().unwrap().0).collect::<Vec<_>>();
+  assert!(sources == [0, 0, 0, 1, 0, 0, 0, 1]);
+  assert!(select.starvation(1) == 6 && select.starvation(0) == 2);
+  // an empty ring gives up its turn
+  while select.consumers()[0].occupancy() != 0 { select.pop(); }
+  assert!(select.pop().map(|(index, _)| index) == Some(1));
+}
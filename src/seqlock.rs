@@ -0,0 +1,122 @@
+use crate::ring_queue::volatile_copy;
+use core::{cell::{Cell, UnsafeCell}, mem::MaybeUninit, sync::atomic::{fence, AtomicU64, Ordering}};
+use std::sync::Arc;
+
+/// Cell for sharing a large value, such as a state snapshot, next to a queue.
+/// One writer replaces it wholesale without waiting; readers copy it out and
+/// retry if a write overlapped the copy.
+pub struct SeqLockCell<T> {
+  shared: Arc<SeqLockShared<T>>,
+}
+
+struct SeqLockShared<T> {
+  /// odd while a write is in progress; 64-bit even on 32-bit targets so
+  /// the write numbers handed out by `read_numbered` never wrap
+  sequence: AtomicU64,
+  value: UnsafeCell<T>,
+}
+unsafe impl <T: Copy + Send> Sync for SeqLockShared<T> {}
+
+pub struct SeqLockWriter<T> {
+  shared: Arc<SeqLockShared<T>>,
+}
+
+#[derive(Clone)]
+pub struct SeqLockReader<T> {
+  shared: Arc<SeqLockShared<T>>,
+  /// number of the last value returned by `read_new`
+  last_seen: Cell<u64>,
+}
+
+impl <T: Copy> SeqLockCell<T> {
+  pub fn new(value: T) -> Self {
+    Self { shared: Arc::new(SeqLockShared { sequence: AtomicU64::new(0), value: UnsafeCell::new(value) }) }
+  }
+  /// The reader can be cloned for as many threads as need it.
+  pub fn split(self) -> (SeqLockWriter<T>, SeqLockReader<T>) {
+    (SeqLockWriter { shared: self.shared.clone() }, SeqLockReader { shared: self.shared, last_seen: Cell::new(0) })
+  }
+}
+
+impl <T: Copy> SeqLockWriter<T> {
+  pub fn write(&mut self, value: T) {
+    let sequence = self.shared.sequence.load(Ordering::Relaxed);
+    self.shared.sequence.store(sequence + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    // volatile, since readers may be copying the same bytes right now
+    unsafe { volatile_copy((&raw const value).cast(), self.shared.value.get().cast(), size_of::<T>()) };
+    self.shared.sequence.store(sequence + 2, Ordering::Release);
+  }
+}
+
+impl <T: Copy> SeqLockReader<T> {
+  /// The latest value; spins while the writer is in the middle of replacing it.
+  pub fn read(&self) -> T {
+    self.read_numbered().0
+  }
+  /// The latest value with its number: the initial value is 0 and each
+  /// write counts up by one.
+  pub fn read_numbered(&self) -> (T, u64) {
+    loop {
+      let before = self.shared.sequence.load(Ordering::Acquire);
+      if before % 2 == 1 {
+        core::hint::spin_loop();
+        continue
+      }
+      let mut value = MaybeUninit::<T>::uninit();
+      unsafe { volatile_copy(self.shared.value.get().cast(), value.as_mut_ptr().cast(), size_of::<T>()) };
+      fence(Ordering::Acquire);
+      if self.shared.sequence.load(Ordering::Relaxed) == before {
+        return (unsafe { value.assume_init() }, before / 2)
+      }
+    }
+  }
+  /// The latest value if it was written after the one this reader last got
+  /// from here, with how many values were overwritten unread in between.
+  pub fn read_new(&self) -> Option<(T, u64)> {
+    let (value, number) = self.read_numbered();
+    let last_seen = self.last_seen.replace(number);
+    if number == last_seen { return None }
+    Some((value, number - last_seen - 1))
+  }
+}
+
+#[test]
+fn readers_never_see_torn_values() {
+  let (mut writer, reader) = SeqLockCell::new([0u64; 64]).split();
+  let readers: Vec<_> = (0 .. 2).map(|_| {
+    let reader = reader.clone();
+    std::thread::spawn(move || {
+      for _ in 0 .. 10_000 {
+        let value = reader.read();
+        assert!(value.iter().all(|word| *word == value[0]));
+      }
+    })
+  }).collect();
+  for i in 0 .. 10_000 { writer.write([i; 64]) }
+  for reader in readers { reader.join().unwrap() }
+  assert!(reader.read() == [9_999; 64]);
+}
+
+#[test]
+fn read_new_counts_overwritten_values() {
+  let (mut writer, reader) = SeqLockCell::new(0u32).split();
+  assert!(reader.read_new().is_none());
+  writer.write(1);
+  assert!(reader.read_new() == Some((1, 0)));
+  assert!(reader.read_new().is_none());
+  for value in 2 .. 6 { writer.write(value) }
+  assert!(reader.read_new() == Some((5, 3)));
+  assert!(reader.read_numbered() == (5, 5));
+}
+
+#[test]
+fn write_numbers_go_past_u32() {
+  let (mut writer, reader) = SeqLockCell::new(0u32).split();
+  writer.shared.sequence.store(2 * u64::from(u32::MAX), Ordering::Relaxed);
+  assert!(reader.read_new() == Some((0, u64::from(u32::MAX) - 1)));
+  writer.write(1);
+  writer.write(2);
+  assert!(reader.read_new() == Some((2, 1)));
+  assert!(reader.read_numbered() == (2, u64::from(u32::MAX) + 2));
+}
@@ -0,0 +1,78 @@
+//! Deterministic single-threaded scheduler, enabled by the `sim` feature
+//! for unit-testing systems built on the queue, e.g. discrete-event
+//! simulations of a pipeline.
+//!
+//! Every producer and consumer is a task closure that does one step of work
+//! per call, and `Sim::step` picks which task runs next from a seeded
+//! generator, so a seed always reproduces the same interleaving.
+//!
+//! This is only a scheduler: the queue keeps its atomics under `sim`, they
+//! are not swapped for plain counters. That's what makes runs reproducible
+//! anyway, since tasks never run concurrently, but it also means the feature
+//! doesn't take the atomics' cost out of a simulation, and it can't explore
+//! orderings that only show up with real threads (use `interleave-hooks` or
+//! the soak binary for those).
+
+/// Scheduler over step-wise tasks, each returning false once it is done.
+pub struct Sim<'a> {
+  tasks: Vec<Box<dyn FnMut() -> bool + 'a>>,
+  state: u64,
+  steps: u64,
+}
+
+impl <'a> Sim<'a> {
+  pub fn new(seed: u64) -> Self {
+    // xorshift gets stuck on zero
+    Self { tasks: Vec::new(), state: seed | 1, steps: 0 }
+  }
+  pub fn spawn(&mut self, task: impl FnMut() -> bool + 'a) {
+    self.tasks.push(Box::new(task));
+  }
+  /// Runs one step of a task chosen by the seed, dropping it if it's done.
+  /// Returns false once no tasks are left.
+  pub fn step(&mut self) -> bool {
+    if self.tasks.is_empty() { return false }
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    let index = (self.state % self.tasks.len() as u64) as usize;
+    self.steps += 1;
+    if !(self.tasks[index])() { drop(self.tasks.swap_remove(index)); }
+    return true
+  }
+  /// Steps until every task is done and returns the number of steps taken.
+  /// Tasks waiting on each other forever make this loop forever too.
+  pub fn run(&mut self) -> u64 {
+    while self.step() {}
+    self.steps
+  }
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+}
+
+#[test]
+fn seed_reproduces_the_interleaving() {
+  use std::cell::RefCell;
+  let trace_for = |seed| {
+    let (mut producer, mut consumer) = crate::RingQueue::<u32>::new(2).split();
+    let trace = RefCell::new(Vec::new());
+    let mut sim = Sim::new(seed);
+    let mut next = 0;
+    sim.spawn(|| {
+      if producer.push(next).is_ok() { next += 1 }
+      next < 10
+    });
+    sim.spawn(|| {
+      let item = consumer.pop();
+      trace.borrow_mut().push(item);
+      item != Some(9)
+    });
+    sim.run();
+    drop(sim);
+    trace.into_inner()
+  };
+  let trace = trace_for(7);
+  assert!(trace == trace_for(7));
+  assert!(trace.iter().flatten().copied().eq(0 .. 10));
+}
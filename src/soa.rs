@@ -0,0 +1,295 @@
+use crate::ring_queue::{alloc_ring_memory, bump_index, capacity_overflow, capacity_problem, check_capacity, dealloc_ring_memory, indexing_adjusted_capacity, Metadata};
+use crate::TryNewError;
+use core::{alloc::Layout, marker::PhantomData, sync::atomic::Ordering};
+use std::sync::Arc;
+
+/// Item type that can be stored field by field in parallel column arrays.
+///
+/// Implemented for tuples of up to four elements; structs can implement it
+/// by hand, mapping each field to a column.
+///
+/// # Safety
+///
+/// `write` must store exactly the columns described by `layout`, and `read`
+/// must take back ownership of what `write` stored at the same index.
+pub unsafe trait Columns: Sized {
+  const COUNT: usize;
+  fn layout(column: usize) -> Layout;
+  /// # Safety
+  ///
+  /// Each of `bases` must point to an array of its column with room past `index`.
+  unsafe fn write(self, bases: &[*mut u8], index: usize);
+  /// # Safety
+  ///
+  /// `index` must hold an item stored by `write`; it is moved out.
+  unsafe fn read(bases: &[*mut u8], index: usize) -> Self;
+}
+
+/// Names the field type of column `C`.
+pub trait Column<const C: usize>: Columns {
+  type Field;
+}
+
+macro tuple_columns($count:literal; $($index:tt $name:ident),+) {
+  unsafe impl <$($name),+> Columns for ($($name,)+) {
+    const COUNT: usize = $count;
+    fn layout(column: usize) -> Layout {
+      [$(Layout::new::<$name>()),+][column]
+    }
+    unsafe fn write(self, bases: &[*mut u8], index: usize) {
+      $( unsafe { bases[$index].cast::<$name>().add(index).write(self.$index) }; )+
+    }
+    unsafe fn read(bases: &[*mut u8], index: usize) -> Self {
+      ($( unsafe { bases[$index].cast::<$name>().add(index).read() }, )+)
+    }
+  }
+}
+
+macro tuple_column(($($all:ident),+), $index:tt, $field:ident) {
+  impl <$($all),+> Column<$index> for ($($all,)+) {
+    type Field = $field;
+  }
+}
+
+tuple_columns!(2; 0 A, 1 B);
+tuple_columns!(3; 0 A, 1 B, 2 C);
+tuple_columns!(4; 0 A, 1 B, 2 C, 3 D);
+tuple_column!((A, B), 0, A);
+tuple_column!((A, B), 1, B);
+tuple_column!((A, B, C), 0, A);
+tuple_column!((A, B, C), 1, B);
+tuple_column!((A, B, C), 2, C);
+tuple_column!((A, B, C, D), 0, A);
+tuple_column!((A, B, C, D), 1, B);
+tuple_column!((A, B, C, D), 2, C);
+tuple_column!((A, B, C, D), 3, D);
+
+/// Ring queue that keeps each column of `T` in its own array, so a consumer
+/// looking at one field per item only touches that field's cache lines.
+/// Uses the same index scheme as `RingQueue`. Unlike `RingQueue`, queued
+/// items are dropped and the memory freed when it goes out of scope.
+///
+/// Enqueuing and dequeuing take `&mut self`, so a producer and a consumer
+/// on different threads go through the halves from `split`.
+pub struct SoaRingQueue<T: Columns> {
+  metadata: Box<Metadata>,
+  bases: Box<[*mut u8]>,
+  capacity: usize,
+  _phantom: PhantomData<T>,
+}
+// shared references only peek, handing out `&` to the fields
+unsafe impl <T: Columns + Send + Sync> Sync for SoaRingQueue<T> {}
+unsafe impl <T: Columns + Send> Send for SoaRingQueue<T> {}
+
+/// Sending half of a split `SoaRingQueue`.
+pub struct SoaProducer<T: Columns> {
+  queue: Arc<SoaRingQueue<T>>,
+}
+/// Receiving half of a split `SoaRingQueue`. It is only shared between
+/// threads if the columns can be:
+///
+/// ```compile_fail
+/// # use atomic_spsc_queue::SoaRingQueue;
+/// # use core::cell::Cell;
+/// let (_producer, consumer) = SoaRingQueue::<(Cell<u32>, u8)>::new(2).split();
+/// std::thread::scope(|s| {
+///   s.spawn(|| consumer.peek_column::<0>().map(Cell::get));
+/// });
+/// ```
+pub struct SoaConsumer<T: Columns> {
+  queue: Arc<SoaRingQueue<T>>,
+}
+// Each half touches the queue only from its own side, so moving it to
+// another thread needs just `T: Send`; sharing one stays bound to `Arc`'s
+// rules, i.e. needs `T: Sync` as well.
+unsafe impl <T: Columns + Send> Send for SoaProducer<T> {}
+unsafe impl <T: Columns + Send> Send for SoaConsumer<T> {}
+
+impl <T: Columns> SoaRingQueue<T> {
+  pub fn new(capacity: usize) -> Self {
+    check_capacity(capacity);
+    let layouts = Self::column_layouts(capacity).unwrap_or_else(|_| capacity_overflow(capacity));
+    let bases = Self::alloc_columns(&layouts).unwrap_or_else(|layout| std::alloc::handle_alloc_error(layout));
+    Self::from_bases(bases, capacity)
+  }
+  /// Like `new`, but reports an unusable capacity or a failed allocation
+  /// instead of panicking or aborting.
+  pub fn try_new(capacity: usize) -> Result<Self, TryNewError> {
+    if capacity_problem(capacity).is_some() { return Err(TryNewError::InvalidCapacity) }
+    let layouts = Self::column_layouts(capacity)?;
+    let bases = Self::alloc_columns(&layouts).map_err(|_| TryNewError::AllocFailed)?;
+    Ok(Self::from_bases(bases, capacity))
+  }
+  fn from_bases(bases: Box<[*mut u8]>, capacity: usize) -> Self {
+    Self { metadata: Box::new(Metadata::initial(capacity)), bases, capacity, _phantom: PhantomData }
+  }
+  fn column_layouts(capacity: usize) -> Result<Vec<Layout>, TryNewError> {
+    (0 .. T::COUNT).map(|column| Self::column_layout(column, capacity)).collect()
+  }
+  /// Allocates every column, or frees the ones already allocated and returns
+  /// the layout that failed.
+  fn alloc_columns(layouts: &[Layout]) -> Result<Box<[*mut u8]>, Layout> {
+    let mut bases = Vec::with_capacity(layouts.len());
+    for layout in layouts {
+      if layout.size() == 0 {
+        bases.push(core::ptr::without_provenance_mut(layout.align()));
+        continue
+      }
+      let Some(base) = alloc_ring_memory(*layout, 0) else {
+        for (base, layout) in bases.into_iter().zip(layouts) {
+          if layout.size() != 0 { unsafe { dealloc_ring_memory(base, *layout, 0) } }
+        }
+        return Err(*layout)
+      };
+      bases.push(base.as_ptr());
+    }
+    Ok(bases.into_boxed_slice())
+  }
+  pub fn enqueue_item(&mut self, item: T) -> Result<(), T> {
+    unsafe { self.put(item) }
+  }
+  #[must_use]
+  pub fn dequeue_item(&mut self) -> Option<T> {
+    unsafe { self.take_next() }
+  }
+  /// # Safety
+  ///
+  /// The caller must be the only producer.
+  unsafe fn put(&self, item: T) -> Result<(), T> {
+    let write_index = self.metadata.write_index.load(Ordering::Relaxed);
+    let next_write_index = bump_index(write_index, self.capacity);
+    if next_write_index == self.metadata.read_index.load(Ordering::Acquire) { return Err(item) }
+    unsafe { item.write(&self.bases, write_index as usize) };
+    self.metadata.write_index.store(next_write_index, Ordering::Release);
+    return Ok(())
+  }
+  /// Column `C` of the next item, leaving the item queued. The item can't
+  /// be dequeued while the returned reference is alive:
+  ///
+  /// ```compile_fail
+  /// # use atomic_spsc_queue::SoaRingQueue;
+  /// let mut q = SoaRingQueue::<(u32, String)>::new(2);
+  /// q.enqueue_item((1, String::from("one"))).unwrap();
+  /// let name = q.peek_column::<1>().unwrap();
+  /// let _ = q.dequeue_item();
+  /// assert!(name == "one");
+  /// ```
+  pub fn peek_column<const C: usize>(&self) -> Option<&<T as Column<C>>::Field> where T: Column<C> {
+    let next_index = self.next_readable()?;
+    Some(unsafe { &*self.bases[C].cast::<<T as Column<C>>::Field>().add(next_index as usize) })
+  }
+  pub fn split(self) -> (SoaProducer<T>, SoaConsumer<T>) {
+    let queue = Arc::new(self);
+    (SoaProducer { queue: queue.clone() }, SoaConsumer { queue })
+  }
+
+  /// # Safety
+  ///
+  /// The caller must be the only consumer, with no reference from
+  /// `peek_column` alive.
+  unsafe fn take_next(&self) -> Option<T> {
+    let next_index = self.next_readable()?;
+    let item = unsafe { T::read(&self.bases, next_index as usize) };
+    self.metadata.read_index.store(next_index, Ordering::Release);
+    return Some(item)
+  }
+
+  fn next_readable(&self) -> Option<u32> {
+    let next_index = bump_index(self.metadata.read_index.load(Ordering::Relaxed), self.capacity);
+    if next_index == self.metadata.write_index.load(Ordering::Acquire) { return None }
+    return Some(next_index)
+  }
+  fn column_layout(column: usize, capacity: usize) -> Result<Layout, TryNewError> {
+    let field = T::layout(column);
+    let size = field.size().checked_mul(indexing_adjusted_capacity(capacity)).ok_or(TryNewError::CapacityOverflow)?;
+    Layout::from_size_align(size, field.align()).map_err(|_| TryNewError::CapacityOverflow)
+  }
+}
+
+impl <T: Columns> SoaProducer<T> {
+  pub fn push(&mut self, item: T) -> Result<(), T> {
+    // the only producer, as the half can't be cloned
+    unsafe { self.queue.put(item) }
+  }
+}
+
+impl <T: Columns> SoaConsumer<T> {
+  #[must_use]
+  pub fn pop(&mut self) -> Option<T> {
+    // the only consumer, and `&mut self` ends any peeked borrow
+    unsafe { self.queue.take_next() }
+  }
+  /// Column `C` of the next item, leaving the item queued; see `SoaRingQueue::peek_column`.
+  ///
+  /// ```compile_fail
+  /// # use atomic_spsc_queue::SoaRingQueue;
+  /// let (mut producer, mut consumer) = SoaRingQueue::<(u32, String)>::new(2).split();
+  /// producer.push((1, String::from("one"))).unwrap();
+  /// let name = consumer.peek_column::<1>().unwrap();
+  /// let _ = consumer.pop();
+  /// assert!(name == "one");
+  /// ```
+  pub fn peek_column<const C: usize>(&self) -> Option<&<T as Column<C>>::Field> where T: Column<C> {
+    self.queue.peek_column::<C>()
+  }
+}
+
+impl <T: Columns> Drop for SoaRingQueue<T> {
+  fn drop(&mut self) {
+    while self.dequeue_item().is_some() {}
+    for (column, base) in self.bases.iter().enumerate() {
+      // laid out the same way in `new`, so this can't fail
+      let Ok(layout) = Self::column_layout(column, self.capacity) else { continue };
+      if layout.size() != 0 { unsafe { dealloc_ring_memory(*base, layout, 0) } }
+    }
+  }
+}
+
+#[test]
+fn columns_round_trip_and_peek() {
+  let mut q = SoaRingQueue::<(u64, [u8; 32], String)>::new(3);
+  for round in 0 .. 3u64 {
+    for i in 0 .. 3 { assert!(q.enqueue_item((round * 3 + i, [i as u8; 32], format!("{i}"))).is_ok()) }
+    assert!(q.enqueue_item((0, [0; 32], String::new())).is_err());
+    for i in 0 .. 3 {
+      assert!(*q.peek_column::<0>().unwrap() == round * 3 + i);
+      let (timestamp, payload, name) = q.dequeue_item().unwrap();
+      assert!(timestamp == round * 3 + i && payload == [i as u8; 32] && name == format!("{i}"));
+    }
+    assert!(q.peek_column::<2>().is_none());
+  }
+  q.enqueue_item((9, [0; 32], String::from("left queued"))).unwrap();
+}
+
+#[test]
+fn split_halves_move_columns_across_threads() {
+  let (mut producer, mut consumer) = SoaRingQueue::<(u32, String)>::new(4).split();
+  std::thread::scope(|s| {
+    s.spawn(move || {
+      for i in 0 .. 100 {
+        let mut item = (i, i.to_string());
+        while let Err(rejected) = producer.push(item) { item = rejected; std::thread::yield_now() }
+      }
+    });
+    let mut expected = 0;
+    while expected < 100 {
+      let Some(id) = consumer.peek_column::<0>().copied() else { std::thread::yield_now(); continue };
+      assert!(id == expected);
+      assert!(consumer.pop() == Some((expected, expected.to_string())));
+      expected += 1;
+    }
+  });
+}
+
+#[test]
+fn try_new_reports_unusable_capacities_and_failed_allocations() {
+  use crate::failpoints::{arm, disarm, Failpoint};
+  assert!(SoaRingQueue::<(u8, u32)>::try_new(0).err() == Some(TryNewError::InvalidCapacity));
+  assert!(SoaRingQueue::<(u8, [u64; 1 << 40])>::try_new(1 << 20).err() == Some(TryNewError::CapacityOverflow));
+  assert!(SoaRingQueue::<(u8, [u8; 1 << 50])>::try_new(1 << 20).err() == Some(TryNewError::CapacityOverflow));
+  arm(Failpoint::Alloc, 1);
+  assert!(SoaRingQueue::<(u8, u32)>::try_new(4).err() == Some(TryNewError::AllocFailed));
+  disarm(Failpoint::Alloc);
+  let mut q = SoaRingQueue::<(u8, u32)>::try_new(4).unwrap();
+  assert!(q.enqueue_item((1, 2)).is_ok() && q.dequeue_item() == Some((1, 2)));
+}
@@ -0,0 +1,66 @@
+use crate::Producer;
+use std::collections::VecDeque;
+
+/// Producer that never rejects an item: pushes that find the ring full go to
+/// a private overflow list, which is moved back into the ring, oldest first,
+/// whenever there is room. Order is preserved across the two.
+///
+/// Items still in the overflow list when the `SpillingProducer` is dropped
+/// are dropped with it; use `into_parts` to keep them.
+pub struct SpillingProducer<T> {
+  producer: Producer<T>,
+  overflow: VecDeque<T>,
+}
+
+impl <T> Producer<T> {
+  pub fn into_spilling(self) -> SpillingProducer<T> {
+    SpillingProducer { producer: self, overflow: VecDeque::new() }
+  }
+}
+
+impl <T> SpillingProducer<T> {
+  pub fn push(&mut self, item: T) {
+    self.flush();
+    if !self.overflow.is_empty() {
+      self.overflow.push_back(item);
+      return
+    }
+    if let Err(item) = self.producer.push(item) {
+      self.overflow.push_back(item);
+    }
+  }
+  /// Moves as many spilled items into the ring as fit.
+  /// Returns true if the overflow list is empty afterwards.
+  pub fn flush(&mut self) -> bool {
+    while let Some(item) = self.overflow.pop_front() {
+      if let Err(item) = self.producer.push(item) {
+        self.overflow.push_front(item);
+        return false
+      }
+    }
+    return true
+  }
+  /// Number of items waiting in the overflow list.
+  pub fn spilled(&self) -> usize {
+    self.overflow.len()
+  }
+  pub fn into_parts(self) -> (Producer<T>, VecDeque<T>) {
+    (self.producer, self.overflow)
+  }
+}
+
+#[test]
+fn spilled_items_keep_their_order() {
+  let (producer, mut consumer) = crate::RingQueue::<u32>::new(2).split();
+  let mut producer = producer.into_spilling();
+  for i in 0 .. 5 { producer.push(i) }
+  assert!(producer.spilled() == 3);
+  assert!(consumer.pop() == Some(0));
+  producer.push(5);
+  assert!(producer.spilled() == 3);
+  let mut received = vec![consumer.pop().unwrap()];
+  while !producer.flush() || received.len() < 5 {
+    if let Some(item) = consumer.pop() { received.push(item) }
+  }
+  assert!(received == [1, 2, 3, 4, 5]);
+}
@@ -0,0 +1,1088 @@
+use crate::{histogram::{OccupancyHistogram, Sampler}, metrics::{QueueEvent, QueueMetrics}, pace::TokenBucket, RingQueue};
+use core::{mem::{ManuallyDrop, MaybeUninit}, ops::{ControlFlow, Deref, DerefMut}, ptr::NonNull, sync::atomic::{fence, AtomicU64, Ordering}};
+use std::{sync::{atomic::AtomicBool, Arc, Mutex}, thread::Thread};
+
+struct Shared<T> {
+  queue: ManuallyDrop<RingQueue<T>>,
+  /// items published since the split, written only by the producer
+  published: AtomicU64,
+  /// items consumed since the split, written only by the consumer
+  consumed: AtomicU64,
+  metrics: Option<QueueMetrics>,
+  /// set by `Consumer::register_thread`, for the producer's `on_blocked` hook
+  consumer_thread: Mutex<Option<ConsumerThread>>,
+  /// set by the consumer after enough empty polls, see `Consumer::detect_idle`
+  consumer_idle: AtomicBool,
+}
+
+impl <T> Shared<T> {
+  #[inline(always)]
+  fn record(&self, event: QueueEvent, count: usize) {
+    if let Some(metrics) = &self.metrics { metrics.record(event, count, || self.queue.occupancy()) }
+  }
+}
+
+/// Bumps a counter that only the calling side writes.
+#[inline]
+fn advance(counter: &AtomicU64, count: usize) {
+  counter.store(counter.load(Ordering::Relaxed) + count as u64, Ordering::Release);
+}
+/// Callback run by one half when the queue crosses an edge the other half may be sleeping on.
+struct EdgeCallback(Box<dyn FnMut() + Send>);
+/// Consumer-side state of `Consumer::detect_idle`.
+struct IdleDetector {
+  after: u32,
+  /// empty pops in a row, saturating
+  empty_polls: u32,
+  on_idle_end: Option<EdgeCallback>,
+}
+
+/// Hook run by `Producer::push_waiting` when the queue is full, see `Producer::on_blocked`.
+type BlockedHook = Box<dyn FnMut(Option<&ConsumerThread>) + Send>;
+
+/// The consumer's thread as recorded by `Consumer::register_thread`, with
+/// the native ids that OS scheduling calls such as `pthread_setschedparam`
+/// or `setpriority` take.
+#[derive(Debug, Clone)]
+pub struct ConsumerThread {
+  thread: Thread,
+  #[cfg(unix)]
+  pthread: libc::pthread_t,
+  #[cfg(target_os = "linux")]
+  tid: libc::pid_t,
+}
+// pthread_t is a pointer on some platforms, but only ever used as an id here
+unsafe impl Send for ConsumerThread {}
+unsafe impl Sync for ConsumerThread {}
+
+impl ConsumerThread {
+  fn current() -> Self {
+    Self {
+      thread: std::thread::current(),
+      #[cfg(unix)]
+      pthread: unsafe { libc::pthread_self() },
+      #[cfg(target_os = "linux")]
+      tid: unsafe { libc::gettid() },
+    }
+  }
+  pub fn thread(&self) -> &Thread {
+    &self.thread
+  }
+  /// For `pthread_setschedparam` and friends.
+  #[cfg(unix)]
+  pub fn pthread(&self) -> libc::pthread_t {
+    self.pthread
+  }
+  /// Kernel thread id, for `setpriority(PRIO_PROCESS, ..)` or `sched_setattr`.
+  #[cfg(target_os = "linux")]
+  pub fn tid(&self) -> libc::pid_t {
+    self.tid
+  }
+}
+
+impl EdgeCallback {
+  /// Calls through an `extern "C"` shim, so a panicking callback aborts
+  /// instead of unwinding through the push or pop that fired it.
+  #[inline(always)]
+  fn fire(&mut self) {
+    extern "C" fn call(callback: &mut Box<dyn FnMut() + Send>) { callback() }
+    call(&mut self.0)
+  }
+}
+
+impl <T> Drop for Shared<T> {
+  fn drop(&mut self) {
+    let mut item = MaybeUninit::<T>::uninit();
+    while self.queue.dequeue_item(&mut item) {
+      unsafe { item.assume_init_drop() };
+    }
+    unsafe { ManuallyDrop::take(&mut self.queue).dispose() };
+  }
+}
+
+/// Sending half of a split `RingQueue`.
+///
+/// There is exactly one per queue: it can't be cloned, and pushing takes `&mut self`.
+///
+/// Both halves are wait-free: `push`, `try_push` and `pop` finish in a bounded
+/// number of steps whatever the other half is doing, even if its thread is
+/// preempted or stopped halfway through an operation. A stalled consumer only
+/// makes pushes fail with `Full`, never block.
+///
+/// ```compile_fail
+/// # use atomic_spsc_queue::RingQueue;
+/// let (producer, _consumer) = RingQueue::<u32>::new(4).split();
+/// let second_producer = producer.clone();
+/// ```
+pub struct Producer<T> {
+  shared: Arc<Shared<T>>,
+  /// items written past the published write index
+  pending: usize,
+  publish_every: usize,
+  on_non_empty: Option<EdgeCallback>,
+  on_blocked: Option<BlockedHook>,
+}
+/// Receiving half of a split `RingQueue`.
+///
+/// Like `Producer`, it can't be cloned or shared between threads.
+///
+/// ```compile_fail
+/// # use atomic_spsc_queue::RingQueue;
+/// fn shared<T: Sync>(_: &T) {}
+/// let (_producer, consumer) = RingQueue::<u32>::new(4).split();
+/// shared(&consumer);
+/// ```
+pub struct Consumer<T> {
+  shared: Arc<Shared<T>>,
+  /// created by the first `pop_paced`
+  pacer: Option<TokenBucket>,
+  on_not_full: Option<EdgeCallback>,
+  sampler: Option<Sampler>,
+  idle: Option<IdleDetector>,
+}
+unsafe impl <T: Send> Send for Producer<T> {}
+unsafe impl <T: Send> Send for Consumer<T> {}
+
+impl <T> RingQueue<T> {
+  /// Splits the queue into its two halves. Items left in the ring are dropped,
+  /// and the allocation freed, once both halves are gone.
+  pub fn split(self) -> (Producer<T>, Consumer<T>) {
+    self.split_inner(None)
+  }
+  /// Like `split`, and reports pushes, pops, rejected pushes and occupancy
+  /// through the `metrics` facade, labelled with `queue = name`.
+  #[cfg(feature = "metrics")]
+  pub fn split_with_metrics(self, name: &str) -> (Producer<T>, Consumer<T>) {
+    self.split_inner(Some(QueueMetrics::new(name)))
+  }
+  fn split_inner(self, metrics: Option<QueueMetrics>) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+      queue: ManuallyDrop::new(self),
+      published: AtomicU64::new(0),
+      consumed: AtomicU64::new(0),
+      metrics,
+      consumer_thread: Mutex::new(None),
+      consumer_idle: AtomicBool::new(false),
+    });
+    (
+      Producer { shared: shared.clone(), pending: 0, publish_every: 1, on_non_empty: None, on_blocked: None },
+      Consumer { shared, pacer: None, on_not_full: None, sampler: None, idle: None },
+    )
+  }
+}
+
+impl <T> Producer<T> {
+  /// Joins the halves back into the queue they were split from, keeping queued items.
+  /// Halves of different queues are handed back unchanged.
+  #[allow(clippy::result_large_err)]
+  pub fn unsplit(mut self, consumer: Consumer<T>) -> Result<RingQueue<T>, (Producer<T>, Consumer<T>)> {
+    if !Arc::ptr_eq(&self.shared, &consumer.shared) { return Err((self, consumer)) }
+    drop(consumer);
+    self.flush();
+    // Both types have a `Drop`, so their fields are moved out one by one;
+    // the patterns are exhaustive, so a new field has to be handled here.
+    let mut this = ManuallyDrop::new(self);
+    let Producer { shared, pending: _, publish_every: _, on_non_empty, on_blocked } = &mut *this;
+    let (shared, on_non_empty, on_blocked) = unsafe { (core::ptr::read(shared), core::ptr::read(on_non_empty), core::ptr::read(on_blocked)) };
+    drop((on_non_empty, on_blocked));
+    let Ok(shared) = Arc::try_unwrap(shared) else { unreachable!() };
+    let mut shared = ManuallyDrop::new(shared);
+    let Shared { queue, published: _, consumed: _, metrics, consumer_thread, consumer_idle: _ } = &mut *shared;
+    let (queue, metrics, consumer_thread) = unsafe { (core::ptr::read(queue), core::ptr::read(metrics), core::ptr::read(consumer_thread)) };
+    drop((metrics, consumer_thread));
+    return Ok(ManuallyDrop::into_inner(queue))
+  }
+  /// Hands the item back if it could not be enqueued.
+  pub fn push(&mut self, item: T) -> Result<(), T> {
+    let Some(slot) = self.write_chunk().first_mut().map(|slot| slot as *mut MaybeUninit<T>) else {
+      self.shared.record(QueueEvent::Reject, 1);
+      return Err(item)
+    };
+    unsafe {
+      self.shared.queue.write_slot(slot, item);
+      self.commit(1);
+    }
+    return Ok(())
+  }
+  /// Publishes written items to the consumer only once `count` of them have
+  /// accumulated, trading latency for fewer stores to the shared write index.
+  /// Items held back this way are published by `flush`, when the ring has to
+  /// wrap or fills up, and when the producer is dropped.
+  pub fn publish_every(&mut self, count: usize) {
+    if count == 0 { panic!("Publish granularity must not be zero") }
+    self.publish_every = count;
+    if self.pending >= count { self.flush() }
+  }
+  /// Publishes every written item.
+  pub fn flush(&mut self) {
+    if self.pending == 0 { return }
+    let published = self.shared.published.load(Ordering::Relaxed);
+    // counted first, so the consumer's position never passes it
+    advance(&self.shared.published, self.pending);
+    unsafe { self.shared.queue.commit_write(self.pending) };
+    self.shared.record(QueueEvent::Push, self.pending);
+    self.pending = 0;
+    if let Some(callback) = &mut self.on_non_empty {
+      fence(Ordering::SeqCst);
+      if self.shared.consumed.load(Ordering::Relaxed) == published { callback.fire() }
+    }
+  }
+  /// Runs `callback` after a publish that finds the queue empty, i.e. once
+  /// per burst rather than once per item, so a consumer that sleeps when it
+  /// runs dry is woken only when there is something new.
+  ///
+  /// Edges are not missed as long as the consumer, after registering for the
+  /// wakeup and before going to sleep, issues `fence(SeqCst)` and tries to
+  /// pop once more. The callback runs on the producer's thread and must not
+  /// panic; a panic aborts the process.
+  pub fn on_non_empty(&mut self, callback: impl FnMut() + Send + 'static) {
+    self.on_non_empty = Some(EdgeCallback(Box::new(callback)));
+  }
+  /// Total number of items published since the split.
+  pub fn position(&self) -> u64 {
+    self.shared.published.load(Ordering::Relaxed)
+  }
+  /// Runs `hook` whenever `push_waiting` starts waiting on a full queue,
+  /// with the consumer's thread if it called `register_thread`. The queue
+  /// changes no priorities itself; the hook can use the thread's native ids
+  /// to boost it until it catches up, so a low-priority consumer can't hold
+  /// up a high-priority producer indefinitely.
+  pub fn on_blocked(&mut self, hook: impl FnMut(Option<&ConsumerThread>) + Send + 'static) {
+    self.on_blocked = Some(Box::new(hook));
+  }
+  pub(crate) fn blocked(&mut self) {
+    let Some(hook) = &mut self.on_blocked else { return };
+    let consumer_thread = self.shared.consumer_thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    hook(consumer_thread.as_ref());
+  }
+  /// Starts handing the producer over to another thread. Held-back items are
+  /// published first, and the release fence here pairs with the acquire
+  /// fence in `ProducerHandoff::resume`, so the new thread sees everything
+  /// the old one wrote even if the handoff itself travels through relaxed
+  /// atomics or memory the compiler knows nothing about.
+  pub fn transfer_to_thread(mut self) -> ProducerHandoff<T> {
+    self.flush();
+    fence(Ordering::Release);
+    ProducerHandoff { producer: self }
+  }
+}
+
+/// Producer on its way to another thread; see `Producer::transfer_to_thread`.
+pub struct ProducerHandoff<T> {
+  producer: Producer<T>,
+}
+impl <T> ProducerHandoff<T> {
+  /// Finishes the handoff on the thread that takes over the producer.
+  pub fn resume(self) -> Producer<T> {
+    fence(Ordering::Acquire);
+    self.producer
+  }
+}
+
+impl <T> Drop for Producer<T> {
+  fn drop(&mut self) {
+    self.flush();
+  }
+}
+
+impl <T> Producer<T> {
+  /// Free slots at the write position that can be filled in place,
+  /// up to the point where the ring wraps. Empty when full.
+  pub fn write_chunk(&mut self) -> &mut [MaybeUninit<T>] {
+    let mut chunk = unsafe { &mut *self.shared.queue.writable_chunk() };
+    if chunk.len() == self.pending && self.pending != 0 {
+      // held back items block the rest of the ring until they are published
+      self.flush();
+      chunk = unsafe { &mut *self.shared.queue.writable_chunk() };
+    }
+    return chunk.get_mut(self.pending ..).unwrap_or_default()
+  }
+  /// Constructs the item straight in the next free slot.
+  /// Returns false, without calling `make`, if the queue is full.
+  #[must_use]
+  pub fn push_with(&mut self, make: impl FnOnce() -> T) -> bool {
+    let Some(slot) = self.write_chunk().first_mut() else { return false };
+    slot.write(make());
+    unsafe { self.commit(1) };
+    return true
+  }
+  /// Lets `fill` build the item in the next free slot and publishes it only if
+  /// `fill` succeeds; on error the ring is left as it was. Returns `Ok(false)`,
+  /// without calling `fill`, if the queue is full.
+  ///
+  /// # Safety
+  ///
+  /// `fill` must have fully initialized the slot when it returns `Ok`. On
+  /// `Err` the slot is considered uninitialized, so anything already written
+  /// there that needs dropping has to be dropped by `fill` itself.
+  pub unsafe fn push_with_try<E>(&mut self, fill: impl FnOnce(&mut MaybeUninit<T>) -> Result<(), E>) -> Result<bool, E> {
+    let Some(slot) = self.write_chunk().first_mut() else { return Ok(false) };
+    fill(slot)?;
+    unsafe { self.commit(1) };
+    return Ok(true)
+  }
+  /// Clones `item` into the next free slot, without a temporary copy on the stack.
+  #[must_use]
+  pub fn push_clone(&mut self, item: &T) -> bool where T: Clone {
+    self.push_with(|| item.clone())
+  }
+  /// Publishes the first `count` slots of the last `write_chunk`,
+  /// or holds them back according to `publish_every`.
+  ///
+  /// # Safety
+  ///
+  /// Those slots must have been initialized.
+  pub unsafe fn commit(&mut self, count: usize) {
+    debug_assert!(count <= self.write_chunk().len());
+    self.pending += count;
+    if self.pending >= self.publish_every { self.flush() }
+  }
+}
+
+impl <T> Producer<T> {
+  /// Pushes items from `items` while there is room and returns how many
+  /// were pushed. An item is only taken from the iterator once there is a
+  /// slot for it, so whatever doesn't fit stays in `items`.
+  pub fn extend_until_full(&mut self, items: &mut impl Iterator<Item = T>) -> usize {
+    let mut pushed = 0;
+    while !self.write_chunk().is_empty() {
+      let Some(item) = items.next() else { break };
+      if self.push(item).is_err() { unreachable!() }
+      pushed += 1;
+    }
+    return pushed
+  }
+}
+
+/// Best effort: items past the first one that doesn't fit are dropped with
+/// the iterator. Use `extend_until_full` to keep them.
+impl <T> Extend<T> for Producer<T> {
+  fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+    self.extend_until_full(&mut items.into_iter());
+  }
+}
+
+impl <T: Copy> Producer<T> {
+  /// Copies as many leading items of `items` as fit and returns how many that was.
+  pub fn push_slice(&mut self, items: &[T]) -> usize {
+    let mut written = 0;
+    while written < items.len() {
+      let chunk = self.write_chunk();
+      let count = chunk.len().min(items.len() - written);
+      if count == 0 { break }
+      unsafe { core::ptr::copy_nonoverlapping(items[written ..].as_ptr(), chunk.as_mut_ptr().cast::<T>(), count) };
+      unsafe { self.commit(count) };
+      written += count;
+    }
+    return written
+  }
+}
+
+impl <T: Copy> Consumer<T> {
+  /// Queued items that can be read in place, up to the point where the ring wraps.
+  pub fn read_chunk(&self) -> &[T] {
+    unsafe { &*self.shared.queue.readable_chunk() }
+  }
+  /// Hands the first `count` items of the last `read_chunk` back to the producer.
+  pub fn release(&mut self, count: usize) {
+    assert!(count <= self.read_chunk().len(), "Released more items than were readable");
+    unsafe { self.shared.queue.release_read(count) };
+    self.consumed(count);
+  }
+  /// Copies queued items into `out` and returns how many were copied.
+  pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+    let mut read = 0;
+    while read < out.len() {
+      let chunk = self.read_chunk();
+      let count = chunk.len().min(out.len() - read);
+      if count == 0 { break }
+      out[read .. read + count].copy_from_slice(&chunk[.. count]);
+      self.release(count);
+      read += count;
+    }
+    return read
+  }
+}
+
+/// True once the other half is gone. The fence makes everything the peer
+/// published before dropping visible to the caller.
+fn is_peer_dropped<T>(shared: &Arc<Shared<T>>) -> bool {
+  let dropped = Arc::strong_count(shared) == 1;
+  if dropped { core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire) }
+  return dropped
+}
+
+/// Why `Producer::try_push` rejected an item, which is handed back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError<T> {
+  Full(T),
+  /// The queue is frozen, see `RingQueue::freeze`.
+  Paused(T),
+  /// The consumer is gone; retrying can never succeed.
+  Closed(T),
+}
+
+impl <T> PushError<T> {
+  pub fn into_inner(self) -> T {
+    match self {
+      PushError::Full(item) | PushError::Paused(item) | PushError::Closed(item) => item,
+    }
+  }
+}
+
+/// Why `Consumer::try_pop` returned no item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopError {
+  /// Nothing is queued right now, but the producer may push more.
+  Empty,
+  /// The producer has finished and every item it pushed has been popped.
+  Finished,
+}
+
+impl <T> Producer<T> {
+  /// Ends the stream: the consumer pops what is already queued, and then
+  /// `try_pop` reports `Finished`. Dropping the producer does the same.
+  pub fn finish(self) {
+    drop(self)
+  }
+  /// True while the consumer has declared itself idle, see `Consumer::detect_idle`.
+  pub fn is_consumer_idle(&self) -> bool {
+    self.shared.consumer_idle.load(Ordering::Relaxed)
+  }
+  /// True once the consumer has been dropped.
+  pub fn is_closed(&self) -> bool {
+    is_peer_dropped(&self.shared)
+  }
+  /// Like `push`, but tells why the item was rejected.
+  pub fn try_push(&mut self, item: T) -> Result<(), PushError<T>> {
+    if self.is_closed() { return Err(PushError::Closed(item)) }
+    self.push(item).map_err(|item| {
+      if self.shared.queue.is_frozen() { PushError::Paused(item) } else { PushError::Full(item) }
+    })
+  }
+}
+
+impl <T> Consumer<T> {
+  /// True once the producer has been dropped. Items it pushed before that
+  /// can still be popped.
+  pub fn is_closed(&self) -> bool {
+    is_peer_dropped(&self.shared)
+  }
+  /// Runs `callback` after a pop or release that frees room in a full queue,
+  /// once per transition, so a producer that sleeps while the ring is full is
+  /// woken only when it can make progress. The same fence-and-retry rule as
+  /// `Producer::on_non_empty` applies to the sleeping producer.
+  pub fn on_not_full(&mut self, callback: impl FnMut() + Send + 'static) {
+    self.on_not_full = Some(EdgeCallback(Box::new(callback)));
+  }
+  /// Records the occupancy after every `every`-th pop or release into the
+  /// returned histogram, which can be read from any thread to size the ring
+  /// from real traffic.
+  pub fn sample_occupancy(&mut self, every: usize) -> Arc<OccupancyHistogram> {
+    assert!(every != 0, "Sampling interval must not be zero");
+    let histogram = Arc::new(OccupancyHistogram::default());
+    self.sampler = Some(Sampler { histogram: histogram.clone(), every, countdown: every });
+    return histogram
+  }
+  /// Records the calling thread and its native ids as the one consuming, for
+  /// the producer's `Producer::on_blocked` hook. Call it again after moving
+  /// the consumer.
+  pub fn register_thread(&mut self) {
+    *self.shared.consumer_thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(ConsumerThread::current());
+  }
+  /// Declares the consumer idle once `empty_polls` pops in a row found the
+  /// queue empty, until it consumes an item again. The producer reads this
+  /// with `Producer::is_consumer_idle`, e.g. to batch pushes and wake-ups
+  /// while the consumer has backed off, which saves power with spinning
+  /// consumers on battery-powered devices.
+  pub fn detect_idle(&mut self, empty_polls: u32) {
+    assert!(empty_polls != 0, "Idle threshold must not be zero");
+    let on_idle_end = self.idle.take().and_then(|idle| idle.on_idle_end);
+    self.idle = Some(IdleDetector { after: empty_polls, empty_polls: 0, on_idle_end });
+  }
+  /// Runs `callback` when the consumer gets an item after being idle, e.g.
+  /// to switch back from a low-power polling mode. Needs `detect_idle`.
+  pub fn on_idle_end(&mut self, callback: impl FnMut() + Send + 'static) {
+    let Some(idle) = &mut self.idle else { panic!("Idle detection is off") };
+    idle.on_idle_end = Some(EdgeCallback(Box::new(callback)));
+  }
+  pub fn is_idle(&self) -> bool {
+    self.shared.consumer_idle.load(Ordering::Relaxed)
+  }
+  #[inline]
+  fn empty_poll(&mut self) {
+    let Some(idle) = &mut self.idle else { return };
+    idle.empty_polls = idle.empty_polls.saturating_add(1);
+    if idle.empty_polls == idle.after { self.shared.consumer_idle.store(true, Ordering::Relaxed) }
+  }
+  /// Hands `count` consumed slots back to the producer.
+  #[inline]
+  fn consumed(&mut self, count: usize) {
+    if let Some(idle) = &mut self.idle {
+      if idle.empty_polls >= idle.after {
+        self.shared.consumer_idle.store(false, Ordering::Relaxed);
+        if let Some(callback) = &mut idle.on_idle_end { callback.fire() }
+      }
+      idle.empty_polls = 0;
+    }
+    let consumed = self.shared.consumed.load(Ordering::Relaxed);
+    advance(&self.shared.consumed, count);
+    self.shared.record(QueueEvent::Pop, count);
+    if let Some(sampler) = &mut self.sampler { sampler.tick(|| self.shared.queue.occupancy()) }
+    if let Some(callback) = &mut self.on_not_full {
+      fence(Ordering::SeqCst);
+      let published = self.shared.published.load(Ordering::Relaxed);
+      if published - consumed >= self.shared.queue.capacity() as u64 { callback.fire() }
+    }
+  }
+  /// Like `pop`, but tells an empty queue that may still fill up apart from
+  /// the end of the stream.
+  pub fn try_pop(&mut self) -> Result<T, PopError> {
+    let finished = self.is_closed();
+    if let Some(item) = self.pop() { return Ok(item) }
+    // anything pushed before the producer was dropped would have been popped
+    if finished { Err(PopError::Finished) } else { Err(PopError::Empty) }
+  }
+  /// Items waiting to be consumed.
+  pub fn occupancy(&self) -> usize {
+    self.shared.queue.occupancy()
+  }
+  /// Pops an item only if the token bucket allows it: tokens accrue at `rate`
+  /// per second up to `burst`, and each popped item spends one. Returns None
+  /// when rate limited, leaving the backlog in the queue.
+  #[must_use]
+  pub fn pop_paced(&mut self, rate: f64, burst: u32) -> Option<T> {
+    let now = std::time::Instant::now();
+    let pacer = self.pacer.get_or_insert_with(|| TokenBucket::full(burst, now));
+    pacer.refill(rate, burst, now);
+    if !pacer.has_token() { return None }
+    let item = self.pop()?;
+    if let Some(pacer) = &mut self.pacer { pacer.spend() }
+    return Some(item)
+  }
+  #[must_use]
+  pub fn pop(&mut self) -> Option<T> {
+    let mut item = MaybeUninit::uninit();
+    if !self.shared.queue.dequeue_item(&mut item) { self.empty_poll(); return None }
+    self.consumed(1);
+    return Some(unsafe { item.assume_init() })
+  }
+  /// Total number of items consumed since the split.
+  pub fn position(&self) -> u64 {
+    self.shared.consumed.load(Ordering::Relaxed)
+  }
+  /// How far the consumer trails the producer, in items. Unlike `occupancy`
+  /// it follows the two positions, so it ignores items the producer has
+  /// written but not yet published.
+  pub fn lag(&self) -> u64 {
+    self.shared.published.load(Ordering::Acquire).saturating_sub(self.position())
+  }
+  /// The item `n` places after the next one, left in the queue.
+  pub(crate) fn peek_nth(&self, n: usize) -> Option<&T> {
+    let item = self.shared.queue.peek_nth(n)?;
+    Some(unsafe { item.as_ref() })
+  }
+  /// Pops everything queued right now, in order.
+  pub fn collect_remaining(&mut self) -> Vec<T> {
+    self.drain().collect()
+  }
+  /// Iterator popping items until the queue is found empty. Items pushed
+  /// while it runs are included.
+  pub fn drain(&mut self) -> Drain<'_, T> {
+    Drain { consumer: self }
+  }
+  /// The next item, left in the queue.
+  pub fn peek(&self) -> Option<&T> {
+    let item = self.shared.queue.peek_item()?;
+    Some(unsafe { item.as_ref() })
+  }
+  /// Borrows the next item in place. The item is dropped and its slot
+  /// released to the producer when the guard goes out of scope.
+  pub fn pop_ref(&mut self) -> Option<DequeueGuard<'_, T>> {
+    let item = self.shared.queue.peek_item()?;
+    Some(DequeueGuard { consumer: self, item })
+  }
+  /// Runs `f` on up to `max` queued items in order, in place. Each item `f`
+  /// continues on is dropped and its slot released; the item it breaks on
+  /// stays queued along with everything after it, so a failed downstream
+  /// write is retried on the next call instead of losing the item. Returns
+  /// how many items were processed and whether `f` broke.
+  pub fn process_batch<B>(&mut self, max: usize, mut f: impl FnMut(&mut T) -> ControlFlow<B>) -> (usize, ControlFlow<B>) {
+    let mut processed = 0;
+    while processed < max {
+      let Some(mut item) = self.shared.queue.peek_item() else { break };
+      if let ControlFlow::Break(reason) = f(unsafe { item.as_mut() }) { return (processed, ControlFlow::Break(reason)) }
+      unsafe {
+        item.drop_in_place();
+        self.shared.queue.release_item();
+      }
+      self.consumed(1);
+      processed += 1;
+    }
+    return (processed, ControlFlow::Continue(()))
+  }
+  /// Streams the currently available items in place, without copying them out.
+  ///
+  /// ```
+  /// # use atomic_spsc_queue::RingQueue;
+  /// let (mut producer, mut consumer) = RingQueue::new(4).split();
+  /// producer.push([7u8; 256]).unwrap();
+  /// let mut items = consumer.iter_in_place();
+  /// while let Some(item) = items.next() {
+  ///   assert!(item.iter().all(|byte| *byte == 7));
+  /// }
+  /// ```
+  pub fn iter_in_place(&mut self) -> InPlaceIter<'_, T> {
+    InPlaceIter { consumer: self }
+  }
+}
+
+/// Popping iterator, see `Consumer::drain`.
+pub struct Drain<'a, T> {
+  consumer: &'a mut Consumer<T>,
+}
+impl <T> Iterator for Drain<'_, T> {
+  type Item = T;
+  fn next(&mut self) -> Option<T> {
+    self.consumer.pop()
+  }
+}
+
+/// Lending iterator over queued items, see `Consumer::iter_in_place`.
+pub struct InPlaceIter<'a, T> {
+  consumer: &'a mut Consumer<T>,
+}
+impl <T> InPlaceIter<'_, T> {
+  /// Guard for the next item; the previous guard has to be dropped first,
+  /// which is what releases its slot.
+  #[allow(clippy::should_implement_trait)]
+  pub fn next(&mut self) -> Option<DequeueGuard<'_, T>> {
+    self.consumer.pop_ref()
+  }
+}
+
+pub struct DequeueGuard<'a, T> {
+  consumer: &'a mut Consumer<T>,
+  item: NonNull<T>,
+}
+impl <T> Deref for DequeueGuard<'_, T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    unsafe { self.item.as_ref() }
+  }
+}
+impl <T> DerefMut for DequeueGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    unsafe { self.item.as_mut() }
+  }
+}
+impl <T> Drop for DequeueGuard<'_, T> {
+  fn drop(&mut self) {
+    unsafe {
+      self.item.drop_in_place();
+      self.consumer.shared.queue.release_item();
+    }
+    self.consumer.consumed(1);
+  }
+}
+
+#[test]
+fn split_halves_move_items_across_threads() {
+  let (mut producer, mut consumer) = RingQueue::<u64>::new(64).split();
+  let sender = std::thread::spawn(move || {
+    for i in 0 .. 10_000u64 {
+      let mut item = i;
+      while let Err(rejected) = producer.push(item) { item = rejected }
+    }
+  });
+  let mut expected = 0;
+  while expected < 10_000 {
+    if let Some(item) = consumer.pop() {
+      assert!(item == expected);
+      expected += 1;
+    }
+  }
+  sender.join().unwrap();
+}
+
+#[test]
+fn dequeue_guard_drops_item_and_releases_slot() {
+  let token = Arc::new(());
+  let (mut producer, mut consumer) = RingQueue::new(1).split();
+  assert!(producer.push(token.clone()).is_ok());
+  assert!(producer.push(token.clone()).is_err());
+  {
+    let guard = consumer.pop_ref().unwrap();
+    assert!(Arc::ptr_eq(&guard, &token));
+    assert!(Arc::strong_count(&token) == 2);
+  }
+  assert!(Arc::strong_count(&token) == 1);
+  assert!(consumer.pop_ref().is_none());
+  assert!(producer.push(token.clone()).is_ok());
+  drop(producer);
+  drop(consumer);
+  assert!(Arc::strong_count(&token) == 1);
+}
+
+#[test]
+fn iter_in_place_visits_items_in_order() {
+  let (mut producer, mut consumer) = RingQueue::new(8).split();
+  for i in 0 .. 5u32 { producer.push(i).unwrap() }
+  let mut seen = Vec::new();
+  let mut items = consumer.iter_in_place();
+  while let Some(mut item) = items.next() {
+    *item += 1;
+    seen.push(*item);
+  }
+  assert!(seen == [1, 2, 3, 4, 5]);
+  assert!(consumer.pop().is_none());
+}
+
+#[test]
+fn unsplit_rejoins_only_matching_halves() {
+  let (mut producer_a, consumer_a) = RingQueue::<u32>::new(4).split();
+  let (producer_b, consumer_b) = RingQueue::<u32>::new(4).split();
+  producer_a.push(1).unwrap();
+  let Err((producer_a, consumer_b)) = producer_a.unsplit(consumer_b) else { panic!("halves of different queues joined") };
+  let Ok(queue) = producer_a.unsplit(consumer_a) else { panic!("halves of the same queue rejected") };
+  let (_, mut consumer_a) = queue.split();
+  assert!(consumer_a.pop() == Some(1));
+  unsafe { producer_b.unsplit(consumer_b).ok().unwrap().dispose() };
+}
+
+#[test]
+fn slices_wrap_around_the_ring() {
+  let (mut producer, mut consumer) = RingQueue::<u16>::new(5).split();
+  let mut out = [0u16; 8];
+  for round in 0 .. 6u16 {
+    let items = [round, round + 1, round + 2, round + 3];
+    assert!(producer.push_slice(&items) == 4);
+    assert!(producer.push_slice(&items) == 1);
+    assert!(consumer.pop_slice(&mut out) == 5);
+    assert!(out[.. 5] == [round, round + 1, round + 2, round + 3, round]);
+  }
+  assert!(consumer.read_chunk().is_empty());
+}
+
+#[test]
+fn push_clone_fills_until_full() {
+  let item = Arc::new(String::from("shared"));
+  let (mut producer, mut consumer) = RingQueue::new(2).split();
+  assert!(producer.push_clone(&item));
+  assert!(producer.push_clone(&item));
+  assert!(!producer.push_clone(&item));
+  assert!(Arc::strong_count(&item) == 3);
+  assert!(*consumer.pop().unwrap() == "shared");
+  assert!(producer.push_with(|| Arc::new(String::from("made"))));
+  drop(consumer);
+  drop(producer);
+  assert!(Arc::strong_count(&item) == 1);
+}
+
+#[test]
+fn failed_push_with_try_publishes_nothing() {
+  let (mut producer, mut consumer) = RingQueue::<[u8; 4]>::new(1).split();
+  let parse = |text: &str, slot: &mut MaybeUninit<[u8; 4]>| -> Result<(), ()> {
+    let bytes = text.as_bytes().try_into().map_err(|_| ())?;
+    slot.write(bytes);
+    Ok(())
+  };
+  assert!(unsafe { producer.push_with_try(|slot| parse("toolong", slot)) } == Err(()));
+  assert!(consumer.pop().is_none());
+  assert!(unsafe { producer.push_with_try(|slot| parse("abcd", slot)) } == Ok(true));
+  assert!(unsafe { producer.push_with_try(|slot| parse("efgh", slot)) } == Ok(false));
+  assert!(consumer.pop() == Some(*b"abcd"));
+}
+
+#[test]
+fn publish_every_holds_items_back() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(5).split();
+  producer.publish_every(3);
+  producer.push(0).unwrap();
+  producer.push(1).unwrap();
+  assert!(consumer.pop().is_none());
+  producer.push(2).unwrap();
+  assert!((0 .. 3).all(|i| consumer.pop() == Some(i)));
+  // the ring wraps after two more slots, which publishes early
+  for i in 3 .. 8 { producer.push(i).unwrap() }
+  assert!(producer.push(8).is_err());
+  assert!(consumer.pop() == Some(3) && consumer.pop() == Some(4));
+  producer.flush();
+  assert!((5 .. 8).all(|i| consumer.pop() == Some(i)));
+  producer.push(8).unwrap();
+  drop(producer);
+  assert!(consumer.pop() == Some(8));
+}
+
+#[test]
+fn pop_paced_leaves_the_backlog_queued() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(8).split();
+  for i in 0 .. 5 { producer.push(i).unwrap() }
+  // a rate this low accrues nothing within the test
+  assert!(consumer.pop_paced(0.001, 2) == Some(0));
+  assert!(consumer.pop_paced(0.001, 2) == Some(1));
+  assert!(consumer.pop_paced(0.001, 2).is_none());
+  assert!(consumer.occupancy() == 3);
+}
+
+#[test]
+fn try_push_reports_why_it_failed() {
+  let (mut producer, consumer) = RingQueue::<u32>::new(1).split();
+  assert!(producer.try_push(1) == Ok(()));
+  assert!(producer.try_push(2) == Err(PushError::Full(2)));
+  assert!(!producer.is_closed() && !consumer.is_closed());
+  drop(consumer);
+  assert!(producer.is_closed());
+  assert!(producer.try_push(3) == Err(PushError::Closed(3)));
+}
+
+#[test]
+fn positions_count_published_and_consumed_items() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  producer.publish_every(2);
+  for i in 0 .. 3 { producer.push(i).unwrap() }
+  assert!(producer.position() == 2 && consumer.lag() == 2);
+  drop(consumer.pop_ref());
+  let _ = consumer.pop();
+  assert!(consumer.position() == 2 && consumer.lag() == 0);
+  producer.flush();
+  assert!(producer.position() == 3 && consumer.lag() == 1);
+}
+
+#[test]
+fn collect_remaining_drains_in_order() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  for i in 0 .. 4 { producer.push(i).unwrap() }
+  assert!(consumer.pop() == Some(0));
+  producer.push(4).unwrap();
+  assert!(consumer.collect_remaining() == [1, 2, 3, 4]);
+  assert!(consumer.collect_remaining().is_empty());
+}
+
+#[test]
+fn each_half_progresses_while_the_other_is_parked_mid_operation() {
+  use crate::hooks::HookPoint;
+  use std::{sync::mpsc::channel, time::Duration};
+  let points = [
+    HookPoint::ConsumerLoadReadIndex, HookPoint::ConsumerLoadWriteIndex, HookPoint::ConsumerStoreReadIndex,
+    HookPoint::ProducerLoadWriteIndex, HookPoint::ProducerLoadReadIndex, HookPoint::ProducerStoreWriteIndex,
+  ];
+  for park_at in points {
+    let parks_consumer = matches!(park_at,
+      HookPoint::ConsumerLoadReadIndex | HookPoint::ConsumerLoadWriteIndex | HookPoint::ConsumerStoreReadIndex);
+    let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+    assert!(producer.push(0).is_ok());
+    let (parked_tx, parked_rx) = channel::<()>();
+    let (resume_tx, resume_rx) = channel::<()>();
+    let (done_tx, done_rx) = channel::<()>();
+    std::thread::scope(|s| {
+      let mut armed = true;
+      let park = move |point| if armed && point == park_at {
+        armed = false;
+        parked_tx.send(()).unwrap();
+        resume_rx.recv().unwrap();
+      };
+      if parks_consumer {
+        let parked = s.spawn(move || {
+          crate::hooks::set_thread_hook(park);
+          let _ = consumer.pop();
+          crate::hooks::clear_thread_hook();
+        });
+        parked_rx.recv().unwrap();
+        s.spawn(move || {
+          // the ring fills up and further pushes fail instead of waiting on the consumer
+          while producer.try_push(1).is_ok() {}
+          assert!(matches!(producer.try_push(1), Err(PushError::Full(1))));
+          done_tx.send(()).unwrap();
+        });
+        assert!(done_rx.recv_timeout(Duration::from_secs(10)).is_ok(), "producer waited on the parked consumer at {park_at:?}");
+        resume_tx.send(()).unwrap();
+        parked.join().unwrap();
+      } else {
+        let parked = s.spawn(move || {
+          crate::hooks::set_thread_hook(park);
+          let _ = producer.push(1);
+          crate::hooks::clear_thread_hook();
+        });
+        parked_rx.recv().unwrap();
+        s.spawn(move || {
+          while consumer.pop().is_some() {}
+          assert!(consumer.pop().is_none());
+          done_tx.send(()).unwrap();
+        });
+        assert!(done_rx.recv_timeout(Duration::from_secs(10)).is_ok(), "consumer waited on the parked producer at {park_at:?}");
+        resume_tx.send(()).unwrap();
+        parked.join().unwrap();
+      }
+    });
+  }
+}
+
+#[test]
+fn edge_callbacks_fire_once_per_transition() {
+  use std::sync::atomic::AtomicUsize;
+  let non_empty = Arc::new(AtomicUsize::new(0));
+  let not_full = Arc::new(AtomicUsize::new(0));
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(3).split();
+  let count = non_empty.clone();
+  producer.on_non_empty(move || { count.fetch_add(1, Ordering::Relaxed); });
+  let count = not_full.clone();
+  consumer.on_not_full(move || { count.fetch_add(1, Ordering::Relaxed); });
+  for item in 0 .. 3 { assert!(producer.push(item).is_ok()) }
+  assert!(non_empty.load(Ordering::Relaxed) == 1);
+  assert!(consumer.pop() == Some(0));
+  assert!(consumer.pop() == Some(1));
+  assert!(not_full.load(Ordering::Relaxed) == 1);
+  assert!(producer.push(3).is_ok());
+  assert!(consumer.pop() == Some(2));
+  assert!(consumer.pop() == Some(3));
+  assert!(not_full.load(Ordering::Relaxed) == 1);
+  // drained, so the next burst is a new edge
+  for item in 4 .. 6 { assert!(producer.push(item).is_ok()) }
+  assert!(non_empty.load(Ordering::Relaxed) == 2);
+}
+
+#[test]
+fn try_pop_reports_the_end_after_the_queued_items() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  assert!(consumer.try_pop() == Err(PopError::Empty));
+  assert!(producer.push(1).is_ok() && producer.push(2).is_ok());
+  producer.finish();
+  assert!(consumer.try_pop() == Ok(1));
+  assert!(consumer.try_pop() == Ok(2));
+  assert!(consumer.try_pop() == Err(PopError::Finished));
+}
+
+#[test]
+fn extend_stops_at_full_and_drain_empties() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  let mut items = 0 .. 10;
+  assert!(producer.extend_until_full(&mut items) == 4);
+  assert!(items.next() == Some(4));
+  assert!(consumer.drain().take(2).collect::<Vec<_>>() == [0, 1]);
+  producer.extend(10 .. 20);
+  assert!(consumer.drain().collect::<Vec<_>>() == [2, 3, 10, 11]);
+  assert!(consumer.pop().is_none());
+}
+
+#[test]
+fn occupancy_is_sampled_every_nth_pop() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(8).split();
+  let histogram = consumer.sample_occupancy(2);
+  producer.extend(0 .. 8);
+  assert!(consumer.drain().count() == 8);
+  // sampled after the 2nd, 4th, 6th and 8th pop: 6, 4, 2 and 0 left
+  assert!(histogram.samples() == 4);
+  assert!(histogram.percentile(25.0) == Some(0) && histogram.percentile(100.0) == Some(7));
+}
+
+#[test]
+fn handed_off_producer_continues_the_stream() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(8).split();
+  producer.publish_every(4);
+  assert!(producer.push(0).is_ok() && producer.push(1).is_ok());
+  let handoff = producer.transfer_to_thread();
+  // nothing is left held back in the handoff
+  assert!(consumer.occupancy() == 2);
+  std::thread::spawn(move || {
+    let mut producer = handoff.resume();
+    assert!(producer.push(2).is_ok());
+  }).join().unwrap();
+  assert!(consumer.drain().eq(0 .. 3));
+}
+
+#[test]
+fn process_batch_keeps_the_item_it_broke_on() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(8).split();
+  producer.extend(0 .. 6);
+  let mut written = Vec::new();
+  let (processed, flow) = consumer.process_batch(8, |item| {
+    if *item == 3 { return ControlFlow::Break("downstream full") }
+    written.push(*item);
+    ControlFlow::Continue(())
+  });
+  assert!(processed == 3 && flow == ControlFlow::Break("downstream full"));
+  assert!(consumer.process_batch(2, |item| { written.push(*item); ControlFlow::<()>::Continue(()) }).0 == 2);
+  assert!(written == [0, 1, 2, 3, 4]);
+  assert!(consumer.pop() == Some(5));
+}
+
+#[test]
+fn consumer_goes_idle_after_empty_polls() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  consumer.detect_idle(3);
+  let woken = Arc::new(AtomicU64::new(0));
+  let counted = woken.clone();
+  consumer.on_idle_end(move || { counted.fetch_add(1, Ordering::Relaxed); });
+  for _ in 0 .. 2 { assert!(consumer.pop().is_none()) }
+  assert!(!producer.is_consumer_idle());
+  assert!(consumer.pop().is_none());
+  assert!(producer.is_consumer_idle() && consumer.is_idle());
+  producer.extend([1, 2]);
+  assert!(consumer.pop() == Some(1) && consumer.pop() == Some(2));
+  assert!(!producer.is_consumer_idle());
+  assert!(woken.load(Ordering::Relaxed) == 1);
+}
+
+#[test]
+fn consumer_never_passes_the_published_count() {
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  producer.publish_every(4);
+  consumer.on_not_full(|| {});
+  std::thread::scope(|s| {
+    s.spawn(move || {
+      for item in 0 .. 400 {
+        let mut item = item;
+        while let Err(rejected) = producer.push(item) { item = rejected; std::thread::yield_now() }
+      }
+    });
+    let mut expected = 0;
+    while expected < 400 {
+      // each flush makes four items visible at once; popping them all checks the edge against the count
+      while let Some(item) = consumer.pop() {
+        assert!(item == expected && consumer.position() <= consumer.shared.published.load(Ordering::Relaxed));
+        expected += 1;
+      }
+      std::thread::yield_now();
+    }
+  });
+}
+
+#[test]
+fn unsplit_releases_the_callbacks() {
+  let token = Arc::new(());
+  let (mut producer, mut consumer) = RingQueue::<u32>::new(4).split();
+  let captured = [token.clone(), token.clone(), token.clone()];
+  let [non_empty, blocked, not_full] = captured;
+  producer.on_non_empty(move || { let _ = &non_empty; });
+  producer.on_blocked(move |_| { let _ = &blocked; });
+  consumer.on_not_full(move || { let _ = &not_full; });
+  consumer.register_thread();
+  assert!(producer.push(1).is_ok());
+  let Ok(queue) = producer.unsplit(consumer) else { unreachable!() };
+  assert!(Arc::strong_count(&token) == 1);
+  assert!(queue.into_vec() == [1]);
+}
+
+#[test]
+fn split_halves_move_items_with_the_slot_copy() {
+  use crate::SlotCopy;
+  use core::sync::atomic::AtomicUsize;
+  static INTO: AtomicUsize = AtomicUsize::new(0);
+  static FROM: AtomicUsize = AtomicUsize::new(0);
+  unsafe extern "C" fn into_slot(src: *const u8, dst: *mut u8, len: usize) {
+    INTO.fetch_add(1, Ordering::Relaxed);
+    unsafe { core::ptr::copy_nonoverlapping(src, dst, len) }
+  }
+  unsafe extern "C" fn from_slot(src: *const u8, dst: *mut u8, len: usize) {
+    FROM.fetch_add(1, Ordering::Relaxed);
+    unsafe { core::ptr::copy_nonoverlapping(src, dst, len) }
+  }
+  let queue = unsafe { RingQueue::<u64>::with_slot_copy(4, SlotCopy { into_slot, from_slot }) };
+  let (mut producer, mut consumer) = queue.split();
+  producer.publish_every(2);
+  for item in 0 .. 3 { assert!(producer.push(item).is_ok()) }
+  producer.flush();
+  assert!(consumer.drain().eq(0 .. 3));
+  assert!(INTO.load(Ordering::Relaxed) == 3 && FROM.load(Ordering::Relaxed) == 3);
+}
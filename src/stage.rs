@@ -0,0 +1,101 @@
+use crate::{Consumer, Producer};
+
+/// One step of a ring-to-ring pipeline: pops from the upstream ring, passes
+/// each item through the transform and pushes what it returns downstream.
+///
+/// Outputs are published once per batch. While the downstream ring is full
+/// the stage waits, which in turn backs up the upstream ring. When the
+/// upstream producer is gone and its ring drained, `run` returns and drops
+/// the downstream producer, so the shutdown reaches the next stage; if the
+/// downstream consumer goes away first, dropping the upstream consumer
+/// closes the ring for the stage before.
+pub struct Stage<T, U, F> {
+  consumer: Consumer<T>,
+  producer: Producer<U>,
+  transform: F,
+  batch_size: usize,
+}
+
+impl <T, U, F: FnMut(T) -> Option<U>> Stage<T, U, F> {
+  /// Items for which `transform` returns None are dropped from the stream.
+  pub fn new(consumer: Consumer<T>, producer: Producer<U>, transform: F) -> Self {
+    Self { consumer, producer, transform, batch_size: 32 }
+  }
+  /// Items taken from upstream before the outputs are published; 32 by default.
+  pub fn batch_size(mut self, batch_size: usize) -> Self {
+    assert!(batch_size != 0, "Batch size must not be zero");
+    self.batch_size = batch_size;
+    self
+  }
+  /// Runs the loop on the calling thread until either end shuts down, and
+  /// returns the number of items pushed downstream.
+  pub fn run(mut self) -> u64 {
+    self.producer.publish_every(self.batch_size);
+    let mut forwarded = 0;
+    loop {
+      let upstream_done = self.consumer.is_closed();
+      let mut taken = 0;
+      while taken < self.batch_size {
+        let Some(item) = self.consumer.pop() else { break };
+        taken += 1;
+        let Some(mut output) = (self.transform)(item) else { continue };
+        while let Err(rejected) = self.producer.push(output) {
+          if self.producer.is_closed() { return forwarded }
+          output = rejected;
+          std::thread::yield_now();
+        }
+        forwarded += 1;
+      }
+      self.producer.flush();
+      if self.producer.is_closed() { return forwarded }
+      if taken == 0 {
+        if upstream_done { return forwarded }
+        std::thread::yield_now();
+      }
+    }
+  }
+}
+
+/// Runs `produce` and `consume` on two scoped threads, connected by a fresh
+/// queue of `capacity`, and returns both results once both have finished.
+/// Since the threads are scoped, the closures may borrow from the caller.
+/// A panic on either thread is resumed on the calling one.
+pub fn pipeline<T: Send, A: Send, B: Send>(
+  capacity: usize,
+  produce: impl FnOnce(Producer<T>) -> A + Send,
+  consume: impl FnOnce(Consumer<T>) -> B + Send,
+) -> (A, B) {
+  let (producer, consumer) = crate::RingQueue::new(capacity).split();
+  std::thread::scope(|s| {
+    let producing = s.spawn(move || produce(producer));
+    let consuming = s.spawn(move || consume(consumer));
+    let consumed = consuming.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+    let produced = producing.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+    (produced, consumed)
+  })
+}
+
+#[test]
+fn stages_chain_and_shut_down_in_order() {
+  let (mut source, first_in) = crate::RingQueue::<u32>::new(8).split();
+  let (first_out, second_in) = crate::RingQueue::<u32>::new(4).split();
+  let (second_out, mut sink) = crate::RingQueue::<String>::new(4).split();
+  let (first, second, received) = std::thread::scope(|s| {
+    let first = s.spawn(|| Stage::new(first_in, first_out, |item| (item % 3 != 0).then_some(item * 10)).batch_size(3).run());
+    let second = s.spawn(|| Stage::new(second_in, second_out, |item| Some(item.to_string())).run());
+    s.spawn(move || {
+      for mut item in 0 .. 100 {
+        while let Err(rejected) = source.push(item) { item = rejected }
+      }
+    });
+    let mut received = Vec::new();
+    // the sink sees the end only once both stages have drained and returned
+    while !sink.is_closed() || sink.occupancy() != 0 {
+      if let Some(item) = sink.pop() { received.push(item) }
+    }
+    (first.join().unwrap(), second.join().unwrap(), received)
+  });
+  let expected = (0 .. 100).filter(|item| item % 3 != 0).map(|item| (item * 10).to_string()).collect::<Vec<_>>();
+  assert!(first == expected.len() as u64 && second == first);
+  assert!(received == expected);
+}
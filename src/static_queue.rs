@@ -0,0 +1,117 @@
+use crate::ring_queue::{check_capacity, dequeue_item_prim, enqueue_item_prim, indexing_adjusted_capacity, Metadata, RingQueueRaw};
+use core::{alloc::Layout, cell::UnsafeCell, mem::{offset_of, MaybeUninit}};
+
+/// Ring queue with inline storage for `N` items that can be built in `const`
+/// context, for `static` queues shared with interrupt handlers.
+///
+/// ```
+/// # use atomic_spsc_queue::StaticRingQueue;
+/// # use core::mem::MaybeUninit;
+/// static EVENTS: StaticRingQueue<u32, 64> = StaticRingQueue::new();
+/// assert!(EVENTS.enqueue_item(&MaybeUninit::new(1)));
+/// ```
+///
+/// # Binary size
+///
+/// The empty state has a nonzero read index, so a `static` of this type is
+/// placed in `.data` rather than `.bss` and takes its full
+/// `size_of::<StaticRingQueue<T, N>>()`, slots included, in the binary and
+/// in flash on embedded targets. For large queues where that matters, a
+/// `RingQueue` allocated at startup keeps the image small.
+#[repr(C)]
+pub struct StaticRingQueue<T, const N: usize> {
+  metadata: Metadata,
+  // `N + 2` slots are needed, which can't be spelled as one array length
+  // yet; the two arrays are laid out back to back without padding.
+  slots: [UnsafeCell<MaybeUninit<T>>; N],
+  spare_slots: [UnsafeCell<MaybeUninit<T>>; 2],
+}
+unsafe impl <T: Send, const N: usize> Sync for StaticRingQueue<T, N> {}
+
+impl <T, const N: usize> Default for StaticRingQueue<T, N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl <T, const N: usize> StaticRingQueue<T, N> {
+  pub const fn new() -> Self {
+    const { assert!(offset_of!(Self, spare_slots) == offset_of!(Self, slots) + size_of::<[T; N]>(), "Slot arrays must be contiguous") };
+    check_capacity(N);
+    Self {
+      metadata: Metadata::initial(N),
+      slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+      spare_slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; 2],
+    }
+  }
+  pub const fn capacity(&self) -> usize {
+    N
+  }
+  #[must_use]
+  pub fn enqueue_item(&self, item: &MaybeUninit<T>) -> bool {
+    enqueue_item_prim(&self.raw_queue(), Self::metadata_layout(), Layout::new::<T>(), item.as_ptr().cast())
+  }
+  #[must_use]
+  pub fn dequeue_item(&self, item: &mut MaybeUninit<T>) -> bool {
+    dequeue_item_prim(&self.raw_queue(), Self::metadata_layout(), Layout::new::<T>(), item.as_mut_ptr().cast())
+  }
+
+  /// The prims find the metadata `metadata_layout.size()` bytes before the
+  /// first slot, so the padding between the two fields is counted in.
+  fn metadata_layout() -> Layout {
+    Layout::from_size_align(offset_of!(Self, slots), align_of::<Metadata>()).unwrap()
+  }
+  fn raw_queue(&self) -> RingQueueRaw {
+    debug_assert!(indexing_adjusted_capacity(N) == self.slots.len() + self.spare_slots.len());
+    RingQueueRaw {
+      backing_store: self.slots.as_ptr().cast_mut().cast(),
+      capacity: N,
+      owns_memory: false,
+      slot_copy: None,
+      #[cfg(feature = "mlock")]
+      locked: false,
+      #[cfg(any(debug_assertions, feature = "paranoid"))]
+      slot_states: None,
+    }
+  }
+}
+
+#[test]
+fn static_queue_round_trip() {
+  static Q: StaticRingQueue<u64, 3> = StaticRingQueue::new();
+  for round in 0 .. 4u64 {
+    for i in 0 .. 3 {
+      assert!(Q.enqueue_item(&MaybeUninit::new(round * 10 + i)));
+    }
+    assert!(!Q.enqueue_item(&MaybeUninit::new(0)));
+    let mut out = MaybeUninit::uninit();
+    for i in 0 .. 3 {
+      assert!(Q.dequeue_item(&mut out));
+      assert!(unsafe { out.assume_init() } == round * 10 + i);
+    }
+    assert!(!Q.dequeue_item(&mut out));
+  }
+}
+
+#[test]
+fn static_queue_with_overaligned_items() {
+  #[derive(Clone, Copy, PartialEq, Debug)]
+  #[repr(align(32))]
+  struct Wide(u8);
+  let q = StaticRingQueue::<Wide, 2>::new();
+  assert!(q.enqueue_item(&MaybeUninit::new(Wide(5))));
+  let mut out = MaybeUninit::uninit();
+  assert!(q.dequeue_item(&mut out));
+  assert!(unsafe { out.assume_init() } == Wide(5));
+}
+
+#[test]
+fn static_image_is_metadata_and_slots() {
+  // the binary carries exactly this much per static queue, see "Binary size"
+  assert!(size_of::<StaticRingQueue<u64, 3>>() == size_of::<Metadata>().next_multiple_of(8) + 5 * 8);
+  assert!(size_of::<StaticRingQueue<[u8; 100], 62>>() == size_of::<Metadata>() + 64 * 100);
+  let mut image = [0u8; size_of::<Metadata>()];
+  let empty = StaticRingQueue::<u8, 4>::new();
+  unsafe { core::ptr::copy_nonoverlapping((&raw const empty.metadata).cast::<u8>(), image.as_mut_ptr(), image.len()) };
+  assert!(image.iter().any(|byte| *byte != 0), "empty state is all zeroes, update the Binary size docs");
+}
@@ -0,0 +1,70 @@
+use crate::{Consumer, Producer, RingQueue};
+
+pub type Task = Box<dyn FnOnce() + Send>;
+
+/// Mailbox of closures to run on the consumer's thread.
+///
+/// Each task is moved out of the ring before it runs, so a panicking task
+/// leaves the queue intact. Tasks still queued when both halves are gone are
+/// dropped without running, on the thread that drops the last half.
+pub struct TaskQueue {
+  queue: RingQueue<Task>,
+}
+
+pub struct TaskProducer {
+  producer: Producer<Task>,
+}
+
+pub struct TaskConsumer {
+  consumer: Consumer<Task>,
+}
+
+impl TaskQueue {
+  pub fn new(capacity: usize) -> Self {
+    Self { queue: RingQueue::new(capacity) }
+  }
+  pub fn split(self) -> (TaskProducer, TaskConsumer) {
+    let (producer, consumer) = self.queue.split();
+    (TaskProducer { producer }, TaskConsumer { consumer })
+  }
+}
+
+impl TaskProducer {
+  /// Queues `task`, handing it back boxed if the queue is full.
+  pub fn spawn(&mut self, task: impl FnOnce() + Send + 'static) -> Result<(), Task> {
+    self.producer.push(Box::new(task))
+  }
+}
+
+impl TaskConsumer {
+  /// Runs queued tasks in order until the queue is empty or `budget` tasks
+  /// have run. Returns how many ran.
+  pub fn run_pending(&mut self, budget: usize) -> usize {
+    let mut ran = 0;
+    while ran < budget {
+      let Some(task) = self.consumer.pop() else { break };
+      task();
+      ran += 1;
+    }
+    return ran
+  }
+}
+
+#[test]
+fn run_pending_respects_the_budget() {
+  use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+  let (mut producer, mut consumer) = TaskQueue::new(4).split();
+  let count = Arc::new(AtomicUsize::new(0));
+  for _ in 0 .. 3 {
+    let count = count.clone();
+    assert!(producer.spawn(move || { count.fetch_add(1, Ordering::Relaxed); }).is_ok());
+  }
+  assert!(consumer.run_pending(2) == 2 && count.load(Ordering::Relaxed) == 2);
+  assert!(consumer.run_pending(2) == 1 && count.load(Ordering::Relaxed) == 3);
+  // queued tasks are dropped, not run, along with the queue
+  let dropped = Arc::new(());
+  let held = dropped.clone();
+  assert!(producer.spawn(move || drop(held)).is_ok());
+  drop((producer, consumer));
+  assert!(Arc::strong_count(&dropped) == 1 && count.load(Ordering::Relaxed) == 3);
+}
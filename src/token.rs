@@ -0,0 +1,80 @@
+use crate::Producer;
+use core::{cell::UnsafeCell, sync::atomic::{AtomicU64, Ordering}};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Producer that can be shared between threads which take turns pushing,
+/// for apps that move the producer role around, e.g. between worker threads
+/// of a pool, without switching to an MPSC queue.
+///
+/// Pushing requires the `ProducerToken` that came with it, mutably borrowed,
+/// and there is exactly one, so whoever holds it is the only producer. The
+/// token is handed from thread to thread like any other value.
+pub struct TokenProducer<T> {
+  producer: UnsafeCell<Producer<T>>,
+  id: u64,
+}
+unsafe impl <T: Send> Sync for TokenProducer<T> {}
+
+/// The right to push to one `TokenProducer`; it can't be cloned.
+#[derive(Debug)]
+pub struct ProducerToken {
+  id: u64,
+}
+
+impl <T> Producer<T> {
+  pub fn into_token_producer(self) -> (TokenProducer<T>, ProducerToken) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    (TokenProducer { producer: UnsafeCell::new(self), id }, ProducerToken { id })
+  }
+}
+
+impl <T> TokenProducer<T> {
+  /// Panics if `token` belongs to another `TokenProducer`.
+  pub fn push(&self, token: &mut ProducerToken, item: T) -> Result<(), T> {
+    self.with_producer(token, |producer| producer.push(item))
+  }
+  /// Runs `f` with the underlying producer, for the rest of its API.
+  pub fn with_producer<R>(&self, token: &mut ProducerToken, f: impl FnOnce(&mut Producer<T>) -> R) -> R {
+    assert!(token.id == self.id, "Token belongs to another producer");
+    f(unsafe { &mut *self.producer.get() })
+  }
+  /// Gives the producer back, together with its token.
+  pub fn into_inner(self, token: ProducerToken) -> Producer<T> {
+    assert!(token.id == self.id, "Token belongs to another producer");
+    self.producer.into_inner()
+  }
+}
+
+#[test]
+fn producer_role_moves_with_the_token() {
+  use std::sync::mpsc::channel;
+  let (producer, mut consumer) = crate::RingQueue::<u32>::new(64).split();
+  let (producer, token) = producer.into_token_producer();
+  let (first_tx, first_rx) = channel();
+  let (second_tx, second_rx) = channel();
+  std::thread::scope(|s| {
+    let producer = &producer;
+    s.spawn(move || {
+      let mut token: ProducerToken = first_rx.recv().unwrap();
+      for item in 0 .. 10 { assert!(producer.push(&mut token, item).is_ok()) }
+      second_tx.send(token).unwrap();
+    });
+    s.spawn(move || {
+      let mut token: ProducerToken = second_rx.recv().unwrap();
+      for item in 10 .. 20 { assert!(producer.push(&mut token, item).is_ok()) }
+    });
+    first_tx.send(token).unwrap();
+  });
+  assert!(consumer.drain().eq(0 .. 20));
+}
+
+#[test]
+#[should_panic = "another producer"]
+fn foreign_token_is_rejected() {
+  let (first, _first_consumer) = crate::RingQueue::<u32>::new(4).split();
+  let (second, _second_consumer) = crate::RingQueue::<u32>::new(4).split();
+  let (first, _first_token) = first.into_token_producer();
+  let (_second, mut second_token) = second.into_token_producer();
+  let _ = first.push(&mut second_token, 1);
+}
@@ -0,0 +1,77 @@
+//! Adapters between the ring and `tokio::sync::mpsc`, enabled by the `tokio` feature.
+//!
+//! The ring has no wakeup mechanism, so the side facing the ring polls it and
+//! yields to the runtime whenever there is nothing to move.
+
+use crate::{Consumer, Producer};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Moves items from the ring into `sender`, up to `batch_size` per channel
+/// reservation. Returns once the receiver is closed, or once the producer is
+/// dropped and the ring drained.
+pub async fn forward_to_sender<T>(mut consumer: Consumer<T>, sender: Sender<T>, batch_size: usize) {
+  assert!(batch_size != 0, "Batch size must not be zero");
+  let mut batch = Vec::with_capacity(batch_size);
+  loop {
+    let producer_dropped = consumer.is_closed();
+    while batch.len() < batch_size {
+      let Some(item) = consumer.pop() else { break };
+      batch.push(item);
+    }
+    if batch.is_empty() {
+      if producer_dropped { return }
+      tokio::task::yield_now().await;
+      continue
+    }
+    let Ok(permits) = sender.reserve_many(batch.len()).await else { return };
+    for (permit, item) in permits.zip(batch.drain(..)) {
+      permit.send(item);
+    }
+  }
+}
+
+/// Moves items from `receiver` into the ring, taking up to `batch_size` per
+/// receive and waiting while the ring is full. Returns once the channel is
+/// closed and empty, or once the consumer is dropped.
+pub async fn forward_from_receiver<T>(mut receiver: Receiver<T>, mut producer: Producer<T>, batch_size: usize) {
+  assert!(batch_size != 0, "Batch size must not be zero");
+  let mut batch = Vec::with_capacity(batch_size);
+  loop {
+    if receiver.recv_many(&mut batch, batch_size).await == 0 { return }
+    for mut item in batch.drain(..) {
+      while let Err(rejected) = producer.push(item) {
+        if producer.is_closed() { return }
+        item = rejected;
+        tokio::task::yield_now().await;
+      }
+    }
+  }
+}
+
+#[test]
+fn items_cross_both_bridges() {
+  let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  let (mut ring_producer, ring_consumer) = crate::RingQueue::<u32>::new(16).split();
+  let (back_producer, mut back_consumer) = crate::RingQueue::<u32>::new(16).split();
+  let (sender, receiver) = tokio::sync::mpsc::channel(8);
+  let feeder = std::thread::spawn(move || {
+    for mut item in 0 .. 1000 {
+      while let Err(rejected) = ring_producer.push(item) { item = rejected }
+    }
+  });
+  let collector = std::thread::spawn(move || {
+    let mut received = Vec::new();
+    while received.len() < 1000 {
+      if let Some(item) = back_consumer.pop() { received.push(item) }
+    }
+    received
+  });
+  runtime.block_on(async {
+    let forward = tokio::spawn(forward_to_sender(ring_consumer, sender, 4));
+    let backward = tokio::spawn(forward_from_receiver(receiver, back_producer, 4));
+    forward.await.unwrap();
+    backward.await.unwrap();
+  });
+  feeder.join().unwrap();
+  assert!(collector.join().unwrap() == (0 .. 1000).collect::<Vec<_>>());
+}
@@ -0,0 +1,111 @@
+//! Operation trace recorder, enabled by the `trace` feature only; otherwise
+//! `record` is an empty inline function. It takes a process-wide lock on
+//! every operation, which would serialize the crate's concurrency tests, so
+//! only its own test, run with `--features trace`, turns it on.
+//!
+//! Every index store is logged with its timestamp, thread and the index
+//! values it left behind into a process-wide side ring holding the last
+//! `CAPACITY` operations. When a debug invariant check fails, the records
+//! are printed before the panic, so a race report from the field comes with
+//! the operations that led up to it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+  Push,
+  Pop,
+  /// Publication of this many slots filled in place.
+  CommitWrite(usize),
+  /// Release of this many slots read in place.
+  ReleaseRead(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+  /// Time since the first record of the process.
+  pub elapsed: std::time::Duration,
+  pub thread: std::thread::ThreadId,
+  /// Address of the ring, to tell records of different queues apart.
+  pub queue: usize,
+  pub op: TraceOp,
+  /// Indices after the operation.
+  pub read_index: u32,
+  pub write_index: u32,
+}
+
+#[cfg(feature = "trace")]
+mod imp {
+  use super::{TraceOp, TraceRecord};
+  use crate::ring_queue::Metadata;
+  use core::sync::atomic::Ordering;
+  use std::{collections::VecDeque, sync::{Mutex, OnceLock}, time::Instant};
+
+  pub const CAPACITY: usize = 1024;
+
+  static START: OnceLock<Instant> = OnceLock::new();
+  static RECORDS: Mutex<VecDeque<TraceRecord>> = Mutex::new(VecDeque::new());
+
+  fn lock() -> std::sync::MutexGuard<'static, VecDeque<TraceRecord>> {
+    RECORDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  pub(crate) fn record(queue: *mut (), op: TraceOp, metadata: &Metadata) {
+    let record = TraceRecord {
+      elapsed: START.get_or_init(Instant::now).elapsed(),
+      thread: std::thread::current().id(),
+      queue: queue.addr(),
+      op,
+      read_index: metadata.read_index.load(Ordering::Relaxed),
+      write_index: metadata.write_index.load(Ordering::Relaxed),
+    };
+    let mut records = lock();
+    if records.len() == CAPACITY { records.pop_front(); }
+    records.push_back(record);
+  }
+
+  /// The last `count` operations, oldest first.
+  pub fn recent(count: usize) -> Vec<TraceRecord> {
+    let records = lock();
+    records.iter().skip(records.len().saturating_sub(count)).cloned().collect()
+  }
+
+  /// Prints the recorded operations to stderr.
+  pub fn dump() {
+    for record in recent(CAPACITY) {
+      eprintln!("{:?}", record);
+    }
+  }
+}
+
+#[cfg(not(feature = "trace"))]
+mod imp {
+  use super::TraceOp;
+  use crate::ring_queue::Metadata;
+
+  #[inline(always)]
+  pub(crate) fn record(_queue: *mut (), _op: TraceOp, _metadata: &Metadata) {}
+
+  #[cfg(any(debug_assertions, feature = "paranoid"))]
+  pub(crate) fn dump() {}
+}
+
+pub(crate) use imp::record;
+#[cfg(all(not(feature = "trace"), any(debug_assertions, feature = "paranoid")))]
+pub(crate) use imp::dump;
+#[cfg(feature = "trace")]
+pub use imp::{CAPACITY, recent, dump};
+
+#[cfg(feature = "trace")]
+#[test]
+fn records_index_values_of_each_operation() {
+  let queue = crate::RingQueue::<u32>::new(4);
+  let addr = queue.backing_store().addr();
+  assert!(queue.enqueue_item(&core::mem::MaybeUninit::new(7)));
+  let mut item = core::mem::MaybeUninit::uninit();
+  assert!(queue.dequeue_item(&mut item));
+  let ops: Vec<_> = recent(CAPACITY).into_iter()
+    .filter(|record| record.queue == addr && record.thread == std::thread::current().id())
+    .map(|record| (record.op, record.read_index, record.write_index))
+    .collect();
+  assert!(ops == [(TraceOp::Push, 5, 1), (TraceOp::Pop, 0, 1)]);
+  unsafe { queue.dispose() };
+}
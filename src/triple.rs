@@ -0,0 +1,88 @@
+use core::{cell::UnsafeCell, sync::atomic::{AtomicU8, Ordering}};
+use std::sync::Arc;
+
+/// Latest-value handoff between one writer and one reader that never block
+/// each other: the writer always has a slot of its own to fill, and the
+/// reader always gets the most recently published one. Values published in
+/// between reads are skipped.
+pub struct TripleBuffer<T> {
+  shared: Arc<TripleShared<T>>,
+}
+
+struct TripleShared<T> {
+  slots: [UnsafeCell<T>; 3],
+  /// index of the slot in the middle, plus `FRESH` if it holds a value the
+  /// reader has not seen yet
+  back: AtomicU8,
+}
+unsafe impl <T: Send> Sync for TripleShared<T> {}
+
+const FRESH: u8 = 4;
+
+pub struct TripleBufferWriter<T> {
+  shared: Arc<TripleShared<T>>,
+  slot: u8,
+}
+
+pub struct TripleBufferReader<T> {
+  shared: Arc<TripleShared<T>>,
+  slot: u8,
+}
+
+impl <T: Clone> TripleBuffer<T> {
+  pub fn new(initial: T) -> Self {
+    let slots = [UnsafeCell::new(initial.clone()), UnsafeCell::new(initial.clone()), UnsafeCell::new(initial)];
+    Self { shared: Arc::new(TripleShared { slots, back: AtomicU8::new(1) }) }
+  }
+}
+
+impl <T> TripleBuffer<T> {
+  pub fn split(self) -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    (TripleBufferWriter { shared: self.shared.clone(), slot: 0 }, TripleBufferReader { shared: self.shared, slot: 2 })
+  }
+}
+
+impl <T> TripleBufferWriter<T> {
+  /// The writer's own slot, holding whatever was last written to it. Fill it
+  /// in place and call `publish`.
+  pub fn input(&mut self) -> &mut T {
+    unsafe { &mut *self.shared.slots[self.slot as usize].get() }
+  }
+  /// Hands the filled slot to the reader and takes the stale one back.
+  pub fn publish(&mut self) {
+    let back = self.shared.back.swap(self.slot | FRESH, Ordering::AcqRel);
+    self.slot = back & !FRESH;
+  }
+  pub fn write(&mut self, value: T) {
+    *self.input() = value;
+    self.publish();
+  }
+}
+
+impl <T> TripleBufferReader<T> {
+  /// True if a value was published since the last `read`.
+  pub fn updated(&self) -> bool {
+    self.shared.back.load(Ordering::Relaxed) & FRESH != 0
+  }
+  /// The most recently published value.
+  pub fn read(&mut self) -> &T {
+    if self.updated() {
+      let back = self.shared.back.swap(self.slot, Ordering::AcqRel);
+      self.slot = back & !FRESH;
+    }
+    unsafe { &*self.shared.slots[self.slot as usize].get() }
+  }
+}
+
+#[test]
+fn reader_gets_the_latest_published_value() {
+  let (mut writer, mut reader) = TripleBuffer::new(0u32).split();
+  assert!(*reader.read() == 0 && !reader.updated());
+  writer.write(1);
+  writer.write(2);
+  *writer.input() = 3;
+  assert!(reader.updated());
+  assert!(*reader.read() == 2);
+  writer.publish();
+  assert!(*reader.read() == 3 && *reader.read() == 3);
+}
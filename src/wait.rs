@@ -0,0 +1,153 @@
+use crate::{Consumer, Producer};
+use std::time::Duration;
+
+/// Waiting for the other side that spins first, then yields, then parks,
+/// moving to the next stage after a set number of rounds in the current one.
+///
+/// It keeps a running average of how many rounds past waits took; once
+/// waits usually outlast the spinning, later ones skip it, so steady slow
+/// traffic doesn't burn a core while bursts still get picked up quickly.
+///
+/// Parking uses `park_timeout`, so it also works without anyone calling
+/// `unpark`; pairing it with `Producer::on_non_empty` and `Thread::unpark`
+/// ends a parked wait as soon as there is work.
+#[derive(Debug, Clone)]
+pub struct AdaptiveWait {
+  /// Rounds of `spin_loop` before yielding.
+  pub spins: u32,
+  /// Rounds of `yield_now` before parking.
+  pub yields: u32,
+  pub park_timeout: Duration,
+  /// rounds spent in the current wait
+  round: u32,
+  /// running average of rounds per wait
+  typical: u32,
+}
+
+impl Default for AdaptiveWait {
+  fn default() -> Self {
+    Self { spins: 64, yields: 16, park_timeout: Duration::from_millis(1), round: 0, typical: 0 }
+  }
+}
+
+impl AdaptiveWait {
+  /// One round of waiting, in whichever stage the wait has reached.
+  pub fn wait(&mut self) {
+    let skipped = if self.typical > self.spins + self.yields { self.spins } else { 0 };
+    let round = self.round.saturating_add(skipped);
+    self.round = self.round.saturating_add(1);
+    if round < self.spins {
+      core::hint::spin_loop();
+    } else if round < self.spins + self.yields {
+      std::thread::yield_now();
+    } else {
+      std::thread::park_timeout(self.park_timeout);
+    }
+  }
+  /// Ends the current wait, folding its length into the average.
+  pub fn done(&mut self) {
+    self.typical = ((self.typical as u64 * 3 + self.round as u64) / 4) as u32;
+    self.round = 0;
+  }
+}
+
+impl <T> Producer<T> {
+  /// Pushes, waiting with `wait` while the queue is full. Hands the item
+  /// back once the consumer is gone. Runs the `on_blocked` hook when it
+  /// starts waiting.
+  pub fn push_waiting(&mut self, mut item: T, wait: &mut AdaptiveWait) -> Result<(), T> {
+    let mut blocked = false;
+    loop {
+      match self.push(item) {
+        Ok(()) => break,
+        Err(rejected) if self.is_closed() => { wait.done(); return Err(rejected) }
+        Err(rejected) => item = rejected,
+      }
+      if !blocked {
+        blocked = true;
+        self.blocked();
+      }
+      wait.wait();
+    }
+    wait.done();
+    return Ok(())
+  }
+}
+
+impl <T> Consumer<T> {
+  /// Pops, waiting with `wait` while the queue is empty. Returns None once
+  /// the producer is gone and the queue drained.
+  pub fn pop_waiting(&mut self, wait: &mut AdaptiveWait) -> Option<T> {
+    loop {
+      let finished = self.is_closed();
+      if let Some(item) = self.pop() {
+        wait.done();
+        return Some(item)
+      }
+      if finished { wait.done(); return None }
+      wait.wait();
+    }
+  }
+}
+
+#[test]
+fn long_waits_skip_the_spinning() {
+  let mut wait = AdaptiveWait { park_timeout: Duration::from_micros(10), ..AdaptiveWait::default() };
+  for _ in 0 .. 8 {
+    for _ in 0 .. 200 { wait.wait() }
+    wait.done();
+  }
+  assert!(wait.typical > wait.spins + wait.yields);
+  for _ in 0 .. 3 { wait.wait() }
+  wait.done();
+  // short waits pull the average back down
+  for _ in 0 .. 16 { wait.done() }
+  assert!(wait.typical < wait.spins);
+}
+
+#[test]
+fn waiting_halves_carry_a_stream_to_the_end() {
+  let (mut producer, mut consumer) = crate::RingQueue::<u32>::new(2).split();
+  let received = std::thread::scope(|s| {
+    s.spawn(move || {
+      let mut wait = AdaptiveWait::default();
+      for item in 0 .. 100 { assert!(producer.push_waiting(item, &mut wait).is_ok()) }
+    });
+    let mut wait = AdaptiveWait::default();
+    core::iter::from_fn(|| consumer.pop_waiting(&mut wait)).collect::<Vec<_>>()
+  });
+  assert!(received == (0 .. 100).collect::<Vec<_>>());
+}
+
+#[test]
+fn blocked_producer_sees_the_consumer_thread() {
+  use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+  let (mut producer, mut consumer) = crate::RingQueue::<u32>::new(1).split();
+  let consumer_thread = std::thread::current().id();
+  #[cfg(target_os = "linux")]
+  let consumer_tid = unsafe { libc::gettid() };
+  consumer.register_thread();
+  let blocks = Arc::new(AtomicUsize::new(0));
+  let counted = blocks.clone();
+  producer.on_blocked(move |thread| {
+    // counted first, so a failed check can't leave the consumer waiting
+    counted.fetch_add(1, Ordering::Relaxed);
+    let thread = thread.unwrap();
+    assert!(thread.thread().id() == consumer_thread);
+    #[cfg(target_os = "linux")]
+    assert!(thread.tid() == consumer_tid && thread.tid() != unsafe { libc::gettid() });
+  });
+  // the ring is full before the consumer starts, so the next push has to wait
+  assert!(producer.push(0).is_ok());
+  std::thread::scope(|s| {
+    s.spawn(move || {
+      let mut wait = AdaptiveWait::default();
+      for item in 1 .. 3 { assert!(producer.push_waiting(item, &mut wait).is_ok()) }
+    });
+    while blocks.load(Ordering::Relaxed) == 0 { std::thread::yield_now() }
+    let mut wait = AdaptiveWait::default();
+    while consumer.pop_waiting(&mut wait).is_some() {}
+  });
+  // the hook ran, with its identity check, at least for the push after the fill
+  assert!((1 ..= 2).contains(&blocks.load(Ordering::Relaxed)));
+}
@@ -0,0 +1,28 @@
+//! Link-time proof that the hot paths contain no panicking code, for use from
+//! signal handlers and audio callbacks. `#[no_panic]` turns any reachable
+//! panic into a linker error, which only holds up under optimization: run
+//! with `cargo test --release`, without the debugging features, which add
+//! panics and locks of their own.
+#![cfg(not(any(debug_assertions, feature = "paranoid", feature = "interleave-hooks", feature = "trace", feature = "metrics")))]
+
+use atomic_spsc_queue::{Consumer, Producer, RingQueue};
+use no_panic::no_panic;
+
+#[no_panic]
+fn push(producer: &mut Producer<u64>, item: u64) -> Result<(), u64> {
+  producer.push(item)
+}
+
+#[no_panic]
+fn pop(consumer: &mut Consumer<u64>) -> Option<u64> {
+  consumer.pop()
+}
+
+#[test]
+fn push_and_pop_cannot_panic() {
+  let (mut producer, mut consumer) = RingQueue::new(2).split();
+  assert!(push(&mut producer, 1).is_ok());
+  assert!(push(&mut producer, 2).is_ok());
+  assert!(push(&mut producer, 3).is_err());
+  assert!(pop(&mut consumer) == Some(1));
+}